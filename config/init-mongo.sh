@@ -18,5 +18,4 @@ mongo -- "$MONGO_INITDB_DATABASE" <<EOF
             }
         ]
     });
-    db.createCollection('wd_entities');
 EOF
\ No newline at end of file
@@ -0,0 +1,320 @@
+use crate::backends::{BackendError, FailedDocument, RetryPolicy, StorageBackend};
+use crate::config::{ElasticsearchConfig, IndexNames};
+use crate::model::{IndexedEntity, IndexedRevision};
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Maximum size of the body of a bulk request. Elasticsearch recommends requests of a few MB, and rejects
+/// the ones larger than http.max_content_length (100 MB by default).
+const MAX_BULK_BYTES: usize = 10 * 1024 * 1024;
+
+/// HTTP statuses of a bulk request or item that are worth retrying: too many requests and unavailable nodes
+const TRANSIENT_STATUSES: [u16; 4] = [429, 502, 503, 504];
+
+/// Indexes entities and revisions into Elasticsearch or OpenSearch with the _bulk API, using the entity
+/// id and the revision id as document ids
+pub struct ElasticsearchBackend {
+    client: Client,
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+    api_key: Option<String>,
+    indices: IndexNames,
+    retry: RetryPolicy
+}
+
+/// A document to index, with the serialized JSON sent as its source
+struct BulkItem {
+    index: String,
+    id: Value,
+    source: String
+}
+
+impl ElasticsearchBackend {
+    pub fn new(config: &ElasticsearchConfig, retry: RetryPolicy) -> Result<ElasticsearchBackend, BackendError> {
+        let client = Client::builder().connect_timeout(Duration::from_secs(30)).build()?;
+        Ok(ElasticsearchBackend {
+            client,
+            url: config.get_url().to_string(),
+            username: config.username.clone(),
+            password: config.password.clone(),
+            api_key: config.api_key.clone(),
+            indices: config.indices.clone(),
+            retry
+        })
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self.client.request(method, format!("{}/{}", self.url, path));
+        match (&self.api_key, &self.username) {
+            (Some(api_key), _) => request.header("Authorization", format!("ApiKey {}", api_key)),
+            (None, Some(username)) => request.basic_auth(username, self.password.as_ref()),
+            (None, None) => request
+        }
+    }
+
+    /// Sends a request with a JSON body, failing on any unsuccessful status
+    async fn send_json(&self, method: Method, path: &str, body: Option<&Value>) -> Result<Value, BackendError> {
+        let mut request = self.request(method, path);
+        if let Some(body) = body {
+            request = request.header("Content-Type", "application/json").body(body.to_string());
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let text = response.text().await?;
+        if !status.is_success() {
+            return Err(format!("{} returned {}: {}", path, status, text).into());
+        }
+        Ok(serde_json::from_str(&text).unwrap_or(Value::Null))
+    }
+
+    /// Sends the items in a single bulk request, returning the status and error of each failed item, in the
+    /// same order as the items. The status is missing when the request could not be sent or the response
+    /// lacks the results of some items.
+    async fn send_bulk(&self, items: &[BulkItem]) -> Result<Vec<Option<(Option<u16>, String)>>, (Option<u16>, String)> {
+        let mut body = String::new();
+        for item in items {
+            body.push_str(&json!({"index": {"_index": item.index, "_id": item.id}}).to_string());
+            body.push('\n');
+            body.push_str(&item.source);
+            body.push('\n');
+        }
+
+        let response = self.request(Method::POST, "_bulk")
+            .header("Content-Type", "application/x-ndjson")
+            .body(body)
+            .send().await
+            .map_err(|e| (None, e.to_string()))?;
+        let status = response.status();
+        let text = response.text().await.map_err(|e| (Some(status.as_u16()), e.to_string()))?;
+        if status != StatusCode::OK {
+            return Err((Some(status.as_u16()), text));
+        }
+
+        let response: Value = serde_json::from_str(&text).map_err(|e| (Some(status.as_u16()), e.to_string()))?;
+        let response_items = response["items"].as_array().ok_or((Some(status.as_u16()), text.clone()))?;
+        if response_items.len() != items.len() {
+            // the items without a result may not have been indexed. Failing the whole request retries them
+            // all, which is harmless since documents are indexed by id
+            let message = format!("The bulk response has {} items instead of {}", response_items.len(), items.len());
+            return Err((None, message));
+        }
+        Ok(response_items.iter().map(|item| {
+            let result = &item["index"];
+            let status = result["status"].as_u64().map(|s| s as u16);
+            match result.get("error") {
+                Some(error) if !error.is_null() => {
+                    let message = match (error["type"].as_str(), error["reason"].as_str()) {
+                        (Some(error_type), Some(reason)) => format!("{}: {}", error_type, reason),
+                        _ => error.to_string()
+                    };
+                    Some((status, message))
+                },
+                _ => None
+            }
+        }).collect())
+    }
+
+    /// Indexes the items, retrying the requests and items that fail with transient errors
+    async fn write_items(&self, mut items: Vec<BulkItem>) -> Vec<FailedDocument> {
+        let mut failed = Vec::<FailedDocument>::new();
+        let mut attempt = 0;
+
+        while !items.is_empty() {
+            let errors = match self.send_bulk(&items).await {
+                Ok(errors) => errors,
+                // the whole request failed: network error, overloaded cluster...
+                Err(error) => vec![Some(error); items.len()]
+            };
+
+            let mut retryable = Vec::<BulkItem>::new();
+            for (item, error) in items.into_iter().zip(errors) {
+                match error {
+                    None => (),
+                    Some((status, _)) if is_transient(status) && attempt < self.retry.max_retries => retryable.push(item),
+                    Some((status, message)) => failed.push(FailedDocument {
+                        collection: item.index,
+                        id: item.id,
+                        code: status.map(i32::from),
+                        error: message,
+                        document: serde_json::from_str(&item.source).ok()
+                    })
+                }
+            }
+
+            if !retryable.is_empty() {
+                println!("Retrying {} documents that failed with transient errors", retryable.len());
+                tokio::time::sleep(self.retry.backoff(attempt)).await;
+                attempt += 1;
+            }
+            items = retryable;
+        }
+
+        failed
+    }
+}
+
+/// Whether a failed request or item is worth retrying. Requests that got no response are always retried.
+fn is_transient(status: Option<u16>) -> bool {
+    status.is_none_or(|s| TRANSIENT_STATUSES.contains(&s))
+}
+
+/// Converts an indexed document to the source of an Elasticsearch document: the id is sent separately, and
+/// the timestamp as an RFC 3339 date instead of a BSON date
+fn to_source<T: Serialize>(document: &T, timestamp: Option<String>) -> String {
+    let mut source = serde_json::to_value(document).expect("Error serializing document");
+    if let Value::Object(fields) = &mut source {
+        fields.remove("_id");
+        if let Some(timestamp) = timestamp {
+            fields.insert("timestamp".to_string(), Value::from(timestamp));
+        }
+    }
+    source.to_string()
+}
+
+fn get_entities_mappings() -> Value {
+    json!({
+        "dynamic": false,
+        "properties": {
+            "id": {"type": "long"},
+            "entity_id": {"type": "keyword"},
+            "class_ids": {"type": "keyword"},
+            "inherited_class_ids": {"type": "keyword"},
+            // stored in the source, but not indexed
+            "entity_json": {"type": "object", "enabled": false},
+            "dump_version": {"type": "keyword"}
+        }
+    })
+}
+
+fn get_revisions_mappings() -> Value {
+    json!({
+        "dynamic": false,
+        "properties": {
+            "id": {"type": "long"},
+            "parent_id": {"type": "long"},
+            "entity_id": {"type": "keyword"},
+            "class_ids": {"type": "keyword"},
+            "inherited_class_ids": {"type": "keyword"},
+            "timestamp": {"type": "date"},
+            "username": {"type": "keyword"},
+            "comment": {"type": "text", "fields": {"keyword": {"type": "keyword", "ignore_above": 256}}},
+            "dump_version": {"type": "keyword"},
+            "entity_diff": {
+                "properties": {
+                    "op": {"type": "keyword"},
+                    "path": {"type": "keyword", "ignore_above": 1024},
+                    "from": {"type": "keyword", "ignore_above": 1024},
+                    "value": {"type": "object", "enabled": false},
+                    "section": {"type": "keyword"},
+                    "property": {"type": "keyword"},
+                    "statement_index": {"type": "integer"},
+                    "statement_guid": {"type": "keyword"},
+                    "subpart": {"type": "keyword"},
+                    "language": {"type": "keyword"},
+                    "site": {"type": "keyword"}
+                }
+            }
+        }
+    })
+}
+
+#[async_trait]
+impl StorageBackend for ElasticsearchBackend {
+    async fn create_schema(&self) -> Result<(), BackendError> {
+        let indices = [(&self.indices.entities, get_entities_mappings()), (&self.indices.revisions, get_revisions_mappings())];
+        for (index, mappings) in indices.iter() {
+            let template = json!({
+                "index_patterns": [format!("{}*", index)],
+                "template": {"mappings": mappings}
+            });
+            self.send_json(Method::PUT, &format!("_index_template/{}", index), Some(&template)).await?;
+
+            let exists = self.request(Method::HEAD, index).send().await?.status() == StatusCode::OK;
+            if exists {
+                // templates only apply to new indices. Adds the fields mapped by newer versions.
+                self.send_json(Method::PUT, &format!("{}/_mapping", index), Some(mappings)).await?;
+            } else {
+                self.send_json(Method::PUT, index, None).await?;
+                println!("Created index {}", index);
+            }
+        }
+        Ok(())
+    }
+
+    async fn create_indexes(&self) -> Result<(), BackendError> {
+        // documents are indexed as they are written, refreshing only makes them visible to searches
+        println!("Refreshing indices {} and {}", self.indices.entities, self.indices.revisions);
+        self.send_json(Method::POST, &format!("{},{}/_refresh", self.indices.entities, self.indices.revisions), None).await?;
+        Ok(())
+    }
+
+    async fn get_latest_revision_ids(&self, entity_ids: &[String]) -> Result<HashMap<String, u64>, BackendError> {
+        let query = json!({
+            "size": 0,
+            "query": {"terms": {"entity_id": entity_ids}},
+            "aggs": {"entities": {
+                "terms": {"field": "entity_id", "size": entity_ids.len().max(1)},
+                "aggs": {"latest_revision_id": {"max": {"field": "id"}}}
+            }}
+        });
+        let response = self.send_json(Method::POST, &format!("{}/_search", self.indices.revisions), Some(&query)).await?;
+
+        let buckets = response["aggregations"]["entities"]["buckets"].as_array().cloned().unwrap_or_default();
+        Ok(buckets.iter()
+            .filter_map(|bucket| Some((
+                bucket["key"].as_str()?.to_string(),
+                bucket["latest_revision_id"]["value"].as_f64()? as u64
+            )))
+            .collect())
+    }
+
+    async fn write(&self, entities: Vec<IndexedEntity>, revisions: Vec<IndexedRevision>) -> Vec<FailedDocument> {
+        let mut failed = Vec::<FailedDocument>::new();
+        let mut items = Vec::<BulkItem>::new();
+        for entity in entities.iter() {
+            items.push(BulkItem {
+                index: self.indices.entities.clone(),
+                id: Value::from(entity.entity_id.clone()),
+                source: to_source(entity, None)
+            });
+        }
+        for revision in revisions.iter() {
+            match revision.timestamp.try_to_rfc3339_string() {
+                Ok(timestamp) => items.push(BulkItem {
+                    index: self.indices.revisions.clone(),
+                    id: Value::from(revision.id),
+                    source: to_source(revision, Some(timestamp))
+                }),
+                Err(e) => failed.push(FailedDocument {
+                    collection: self.indices.revisions.clone(),
+                    id: Value::from(revision.id),
+                    code: None,
+                    error: e.to_string(),
+                    document: serde_json::to_value(revision).ok()
+                })
+            }
+        }
+
+        // split the items into requests of bounded size
+        let mut request_items = Vec::<BulkItem>::new();
+        let mut request_bytes = 0;
+        for item in items {
+            let item_bytes = item.source.len();
+            if !request_items.is_empty() && request_bytes + item_bytes > MAX_BULK_BYTES {
+                failed.append(&mut self.write_items(std::mem::take(&mut request_items)).await);
+                request_bytes = 0;
+            }
+            request_bytes += item_bytes;
+            request_items.push(item);
+        }
+        failed.append(&mut self.write_items(request_items).await);
+        failed
+    }
+}
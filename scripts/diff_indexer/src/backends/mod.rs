@@ -0,0 +1,85 @@
+pub mod elasticsearch;
+pub mod mongo;
+pub mod postgres;
+pub mod sqlite;
+
+use crate::model::{IndexedEntity, IndexedRevision};
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use clap::ArgEnum;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Maximum time waited between two retries
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+pub type BackendError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum BackendKind {
+    /// Elasticsearch or OpenSearch cluster, configured with --elasticsearch-url, --config or the ELASTICSEARCH_*
+    /// environment variables
+    Elasticsearch,
+    /// MongoDB server, configured with --mongo-uri, --config or the MONGO_* environment variables
+    Mongo,
+    /// PostgreSQL server, configured with --postgres-url, --config or the POSTGRES_URL environment variable
+    Postgres,
+    /// Local SQLite database file, which doesn't need any service running
+    Sqlite
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum IndexMode {
+    /// Build the indexes before loading the documents
+    Before,
+    /// Build the indexes once all the documents are loaded, which is faster for large loads
+    After,
+    /// Do not build the indexes
+    Skip
+}
+
+/// How many times, and how long to wait between them, failed writes are retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration
+}
+
+impl RetryPolicy {
+    /// Exponential backoff: the wait is doubled on each retry
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff.saturating_mul(2u32.saturating_pow(attempt)).min(MAX_BACKOFF)
+    }
+}
+
+/// A document that could not be written, along with the last error returned for it
+#[derive(Debug, Serialize)]
+pub struct FailedDocument {
+    pub collection: String,
+    #[serde(rename = "_id")]
+    pub id: Value,
+    pub code: Option<i32>,
+    pub error: String,
+    pub document: Option<Value>
+}
+
+/// Storage where the entities and their revisions are indexed. Writes must be idempotent, so indexing
+/// the same entities again replaces them.
+#[async_trait]
+pub trait StorageBackend: Send + Sync {
+    /// Creates the collections or tables, if they don't exist
+    async fn create_schema(&self) -> Result<(), BackendError>;
+
+    /// Builds the indexes used by the queries, if they don't exist
+    async fn create_indexes(&self) -> Result<(), BackendError>;
+
+    /// Returns the id of the latest revision indexed for each of the given entities, leaving out the
+    /// entities without indexed revisions
+    async fn get_latest_revision_ids(&self, entity_ids: &[String]) -> Result<HashMap<String, u64>, BackendError>;
+
+    /// Writes a batch of entities and revisions, returning the ones that could not be written
+    async fn write(&self, entities: Vec<IndexedEntity>, revisions: Vec<IndexedRevision>) -> Vec<FailedDocument>;
+}
@@ -0,0 +1,230 @@
+use crate::backends::{FailedDocument, RetryPolicy};
+
+
+use mongodb::bson::{doc, to_bson, to_document, Bson, Document};
+use mongodb::error::{Error, ErrorKind};
+use mongodb::Database;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Maximum size of the updates sent in a single update command. The server rejects commands over 16 MB.
+const MAX_COMMAND_SIZE: usize = 8 * 1024 * 1024;
+
+/// Maximum number of updates sent in a single update command
+const MAX_COMMAND_UPDATES: usize = 10000;
+
+/// Server error codes that are worth retrying: the retryable write errors of the MongoDB drivers
+/// specification plus write conflicts
+const TRANSIENT_ERROR_CODES: [i32; 13] = [6, 7, 89, 91, 112, 189, 262, 9001, 10107, 11600, 11602, 13435, 13436];
+
+#[derive(Debug, Default)]
+pub struct BulkResult {
+    pub num_upserted: u64,
+    pub num_matched: u64,
+    pub failed: Vec<FailedDocument>
+}
+
+/// Upserts documents keyed by their `_id` using unordered update commands. Documents are fully
+/// replaced if they already exist, so indexing the same documents twice is idempotent.
+/// Transient errors are retried following the given policy; the documents that still can't be written
+/// are returned in `BulkResult::failed`.
+pub async fn bulk_upsert<T: Serialize>(db: &Database, collection_name: &str, documents: &[T],
+                                       retry: &RetryPolicy) -> BulkResult {
+    let mut result = BulkResult::default();
+    let mut chunk = Vec::<Document>::new();
+    let mut chunk_size = 0;
+
+    for document in documents {
+        let (document, document_size) = match to_document(document).and_then(|d| {
+            let mut document_bytes = Vec::new();
+            d.to_writer(&mut document_bytes)?;
+            Ok((d, document_bytes.len()))
+        }) {
+            Ok(d) => d,
+            Err(e) => {
+                let document = serde_json::to_value(document).ok();
+                result.failed.push(FailedDocument {
+                    collection: collection_name.to_string(),
+                    id: document.as_ref().and_then(|d| d.get("_id")).cloned().unwrap_or(Value::Null),
+                    code: None,
+                    error: format!("Error serializing document: {}", e),
+                    document
+                });
+                continue;
+            }
+        };
+
+        if !chunk.is_empty() && (chunk_size + document_size > MAX_COMMAND_SIZE || chunk.len() >= MAX_COMMAND_UPDATES) {
+            write_chunk(db, collection_name, std::mem::take(&mut chunk), retry, &mut result).await;
+            chunk_size = 0;
+        }
+
+        chunk_size += document_size;
+        chunk.push(document);
+    }
+
+    if !chunk.is_empty() {
+        write_chunk(db, collection_name, chunk, retry, &mut result).await;
+    }
+
+    result
+}
+
+/// Writes a chunk of documents. If the whole update command is rejected (e.g. because one of the
+/// documents is too large), the documents are written one by one so only the faulty ones fail.
+async fn write_chunk(db: &Database, collection_name: &str, documents: Vec<Document>,
+                     retry: &RetryPolicy, result: &mut BulkResult) {
+    let (documents, error) = match run_updates(db, collection_name, documents, retry, result).await {
+        Ok(()) => return,
+        Err(e) => e
+    };
+
+    if documents.len() > 1 && !is_transient_error(&error) {
+        println!("Update command on {} failed ({}), writing its {} documents one by one",
+                 collection_name, error, documents.len());
+        for document in documents {
+            if let Err((documents, error)) = run_updates(db, collection_name, vec![document], retry, result).await {
+                add_failed(collection_name, documents, get_error_code(&error), &error.to_string(), result);
+            }
+        }
+    } else {
+        add_failed(collection_name, documents, get_error_code(&error), &error.to_string(), result);
+    }
+}
+
+/// Runs an update command, retrying the documents that fail with transient errors.
+/// If the command itself fails, the documents pending to be written are returned along with the error.
+async fn run_updates(db: &Database, collection_name: &str, mut documents: Vec<Document>, retry: &RetryPolicy,
+                     result: &mut BulkResult) -> Result<(), (Vec<Document>, Error)> {
+    let mut attempt = 0;
+    loop {
+        let updates = documents.iter()
+            .map(|d| Bson::Document(doc! {"q": {"_id": get_id(d)}, "u": d.clone(), "upsert": true}))
+            .collect::<Vec<Bson>>();
+        let mut command = doc! {
+            "update": collection_name,
+            "updates": updates,
+            "ordered": false
+        };
+        // commands don't inherit the write concern of the client
+        if let Some(write_concern) = db.write_concern() {
+            command.insert("writeConcern", to_bson(write_concern).expect("Error serializing write concern"));
+        }
+        let response = db.run_command(command, None).await;
+
+        let response = match response {
+            Ok(response) => response,
+            Err(e) if is_transient_error(&e) && attempt < retry.max_retries => {
+                println!("Transient error writing {} {} documents, retrying: {}", documents.len(), collection_name, e);
+                tokio::time::sleep(retry.backoff(attempt)).await;
+                attempt += 1;
+                continue;
+            },
+            Err(e) => return Err((documents, e))
+        };
+
+        // the documents were written, but not acknowledged by enough members of the replica set.
+        // Upserts are idempotent, so the whole command can be sent again
+        if let Ok(error) = response.get_document("writeConcernError") {
+            let message = error.get_str("errmsg").unwrap_or("write concern error").to_string();
+            if attempt < retry.max_retries {
+                println!("Write concern error writing {} documents, retrying: {}", collection_name, message);
+                tokio::time::sleep(retry.backoff(attempt)).await;
+                attempt += 1;
+                continue;
+            }
+            add_failed(collection_name, documents, error.get_i32("code").ok(), &message, result);
+            return Ok(());
+        }
+
+        let num_written = get_count(&response, "n");
+        let num_upserted = response.get_array("upserted").map(|u| u.len() as u64).unwrap_or(0);
+        result.num_upserted += num_upserted;
+        result.num_matched += num_written.saturating_sub(num_upserted);
+
+        let mut failed_idx = Vec::<(usize, Option<i32>, String)>::new();
+        for error in response.get_array("writeErrors").into_iter().flatten() {
+            if let Bson::Document(error) = error {
+                let idx = get_count(error, "index") as usize;
+                let code = error.get_i32("code").ok();
+                let message = error.get_str("errmsg").unwrap_or("unknown error").to_string();
+                failed_idx.push((idx, code, message));
+            }
+        }
+        if failed_idx.is_empty() {
+            return Ok(());
+        }
+
+        let mut documents_by_idx = documents.into_iter().map(Some).collect::<Vec<Option<Document>>>();
+        let mut retryable = Vec::<Document>::new();
+        let mut errors = Vec::<(Document, Option<i32>, String)>::new();
+        for (idx, code, message) in failed_idx {
+            let document = match documents_by_idx.get_mut(idx).and_then(Option::take) {
+                Some(document) => document,
+                None => continue
+            };
+            if code.is_some_and(|c| TRANSIENT_ERROR_CODES.contains(&c)) && attempt < retry.max_retries {
+                retryable.push(document);
+            } else {
+                errors.push((document, code, message));
+            }
+        }
+
+        for (document, code, message) in errors {
+            add_failed(collection_name, vec![document], code, &message, result);
+        }
+        if retryable.is_empty() {
+            return Ok(());
+        }
+
+        println!("Retrying {} {} documents that failed with transient errors", retryable.len(), collection_name);
+        tokio::time::sleep(retry.backoff(attempt)).await;
+        attempt += 1;
+        documents = retryable;
+    }
+}
+
+fn add_failed(collection_name: &str, documents: Vec<Document>, code: Option<i32>, message: &str, result: &mut BulkResult) {
+    for document in documents {
+        result.failed.push(FailedDocument {
+            collection: collection_name.to_string(),
+            id: get_id(&document).into_relaxed_extjson(),
+            code,
+            error: message.to_string(),
+            document: Some(Bson::Document(document).into_relaxed_extjson())
+        });
+    }
+}
+
+fn get_id(document: &Document) -> Bson {
+    document.get("_id").cloned().unwrap_or(Bson::Null)
+}
+
+/// Counts in command responses may be returned as 32 or 64 bit integers
+fn get_count(document: &Document, key: &str) -> u64 {
+    match document.get(key) {
+        Some(Bson::Int32(n)) => *n as u64,
+        Some(Bson::Int64(n)) => *n as u64,
+        _ => 0
+    }
+}
+
+fn get_error_code(error: &Error) -> Option<i32> {
+    match error.kind.as_ref() {
+        ErrorKind::Command(e) => Some(e.code),
+        _ => None
+    }
+}
+
+/// Whether an error is caused by the network or a temporary server state, so the write may succeed later
+fn is_transient_error(error: &Error) -> bool {
+    if error.contains_label("RetryableWriteError") {
+        return true;
+    }
+
+    match error.kind.as_ref() {
+        ErrorKind::Io(_) | ErrorKind::ConnectionPoolCleared { .. } | ErrorKind::ServerSelection { .. } => true,
+        ErrorKind::Command(e) => TRANSIENT_ERROR_CODES.contains(&e.code),
+        _ => false
+    }
+}
@@ -0,0 +1,151 @@
+use crate::backends::BackendError;
+use crate::config::CollectionNames;
+use crate::export::ShardWriter;
+
+use indicatif::ProgressBar;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::gridfs::GridFsBucket;
+use mongodb::options::{FindOptions, GridFsBucketOptions};
+use mongodb::{Cursor, Database};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use wd_history_core::model::{WikidataItem, WikidataOp, WikidataRevision};
+
+/// Entities to export. Revisions store the classes of their entity, so the same filter applies to both collections.
+#[derive(Debug, Default)]
+pub struct ExportFilter {
+    /// Classes of the exported entities, either direct or inherited
+    pub classes: Vec<String>,
+    pub entity_ids: Option<Vec<String>>
+}
+
+impl ExportFilter {
+    fn to_document(&self) -> Document {
+        let mut filter = Document::new();
+        if !self.classes.is_empty() {
+            filter.insert("$or", vec![
+                doc! {"class_ids": {"$in": &self.classes}},
+                doc! {"inherited_class_ids": {"$in": &self.classes}}
+            ]);
+        }
+        if let Some(entity_ids) = &self.entity_ids {
+            filter.insert("entity_id", doc! {"$in": entity_ids});
+        }
+        filter
+    }
+}
+
+/// Entity as stored by any version of the indexer, including the original dataset
+#[derive(Debug, Deserialize)]
+struct StoredEntity {
+    id: u64,
+    entity_id: String,
+    #[serde(default)]
+    entity_json: Value,
+    entity_json_file: Option<String>
+}
+
+/// Revision as stored by any version of the indexer. Timestamps are dates, or strings in the original dataset.
+#[derive(Debug, Deserialize)]
+struct StoredRevision {
+    id: u64,
+    entity_id: String,
+    parent_id: u64,
+    timestamp: Bson,
+    #[serde(default)]
+    username: String,
+    #[serde(default)]
+    comment: String,
+    entity_diff: Option<Vec<WikidataOp>>,
+    entity_diff_file: Option<String>,
+    entity_diff_chunks: Option<u32>
+}
+
+#[derive(Debug, Deserialize)]
+struct StoredChunk {
+    entity_diff: Vec<WikidataOp>
+}
+
+/// Reads the entities and revisions matching the filter back into items like the ones of the diff files,
+/// with the revisions of each entity sorted by id. Both collections are read sorted by entity id and
+/// merged, so each collection is only read once. Returns the number of exported entities.
+pub async fn export(db: &Database, names: &CollectionNames, filter: &ExportFilter, writer: &mut ShardWriter,
+                    pb: &ProgressBar) -> Result<u64, BackendError> {
+    pb.set_length(db.collection::<Document>(&names.entities).count_documents(filter.to_document(), None).await?);
+    let options = || FindOptions::builder().sort(doc! {"entity_id": 1}).allow_disk_use(true).build();
+    let mut entities = db.collection::<Document>(&names.entities).find(filter.to_document(), options()).await?;
+    let mut revisions = db.collection::<Document>(&names.revisions).find(filter.to_document(), options()).await?;
+    let bucket = db.gridfs_bucket(GridFsBucketOptions::builder().bucket_name(names.large_fields_bucket.clone()).build());
+    let chunks = db.collection::<StoredChunk>(&names.revision_chunks);
+
+    let mut next_revision = next::<StoredRevision>(&mut revisions).await?;
+    let mut num_entities = 0;
+    while let Some(entity) = next::<StoredEntity>(&mut entities).await? {
+        let entity_json = match &entity.entity_json_file {
+            Some(file_id) => load_file(&bucket, file_id).await?,
+            None => entity.entity_json
+        };
+        let mut item = WikidataItem { id: entity.id, entity_id: entity.entity_id, entity_json, revisions: Vec::new() };
+
+        // skip the revisions of entities that were not indexed
+        while next_revision.as_ref().is_some_and(|r| r.entity_id < item.entity_id) {
+            next_revision = next(&mut revisions).await?;
+        }
+        while let Some(revision) = next_revision.take_if(|r| r.entity_id == item.entity_id) {
+            let entity_diff = match (&revision.entity_diff_file, revision.entity_diff_chunks) {
+                (Some(file_id), _) => Some(load_file(&bucket, file_id).await?),
+                (None, Some(_)) => {
+                    let options = FindOptions::builder().sort(doc! {"chunk_index": 1}).build();
+                    let mut cursor = chunks.find(doc! {"revision_id": revision.id as i64}, options).await?;
+                    let mut entity_diff = Vec::new();
+                    while cursor.advance().await? {
+                        entity_diff.append(&mut cursor.deserialize_current()?.entity_diff);
+                    }
+                    Some(entity_diff)
+                },
+                (None, None) => revision.entity_diff
+            };
+            let timestamp = match revision.timestamp {
+                Bson::DateTime(timestamp) => timestamp.try_to_rfc3339_string()?,
+                Bson::String(timestamp) => timestamp,
+                other => return Err(format!("Revision {} has an invalid timestamp: {}", revision.id, other).into())
+            };
+
+            item.revisions.push(WikidataRevision {
+                id: revision.id,
+                parent_id: revision.parent_id,
+                timestamp,
+                username: revision.username,
+                comment: revision.comment,
+                entity_diff
+            });
+            next_revision = next(&mut revisions).await?;
+        }
+
+        item.revisions.sort_by_key(|r| r.id);
+        writer.write(&item)?;
+        num_entities += 1;
+        pb.inc(1);
+    }
+
+    Ok(num_entities)
+}
+
+async fn next<T: DeserializeOwned>(cursor: &mut Cursor<Document>) -> Result<Option<T>, BackendError> {
+    if !cursor.advance().await? {
+        return Ok(None);
+    }
+    let document = cursor.deserialize_current()?;
+    let id = document.get("_id").cloned().unwrap_or(Bson::Null);
+    mongodb::bson::from_document(document)
+        .map(Some)
+        .map_err(|e| format!("Error reading document with _id {}: {}", id, e).into())
+}
+
+/// Reads a JSON file stored in GridFS by the oversize handler
+async fn load_file<T: DeserializeOwned>(bucket: &GridFsBucket, file_id: &str) -> Result<T, BackendError> {
+    let mut contents = Vec::new();
+    bucket.download_to_futures_0_3_writer(Bson::from(file_id), &mut contents).await?;
+    Ok(serde_json::from_slice(&contents)?)
+}
@@ -0,0 +1,119 @@
+use super::schema::{count_invalid_documents, create_collections};
+use crate::config::CollectionNames;
+
+use mongodb::bson::{doc, to_bson, Bson, Document};
+use mongodb::error::Error;
+use mongodb::options::{FindOptions, UpdateOptions};
+use mongodb::Database;
+use wd_history_core::model::PatchOp;
+
+/// Number of documents re-keyed in each round
+const REKEY_BATCH_SIZE: i64 = 1000;
+
+/// Converts in place the documents indexed by previous versions, which stored the revision timestamps as
+/// strings, used generated `_id`s and may lack the inherited classes, and updates the validators of the
+/// collections. Returns false if some documents could not be converted, in which case the validators are
+/// left as they were.
+pub async fn migrate(db: &Database, names: &CollectionNames) -> Result<bool, Error> {
+    let entities = db.collection::<Document>(&names.entities);
+    let revisions = db.collection::<Document>(&names.revisions);
+    let chunks = db.collection::<Document>(&names.revision_chunks);
+
+    // the current validators would reject the documents being converted
+    let options = UpdateOptions::builder().bypass_document_validation(true).build();
+    let result = revisions.update_many(
+        doc! {"timestamp": {"$type": "string"}},
+        vec![doc! {"$set": {"timestamp": {
+            "$dateFromString": {"dateString": "$timestamp", "onError": "$timestamp"}
+        }}}],
+        options.clone()
+    ).await?;
+    println!("Converted the timestamps of {} revisions", result.modified_count);
+
+    let num_rekeyed = rekey(db, &names.entities, "entity_id", "string").await?;
+    println!("Re-keyed {} entities by their entity_id", num_rekeyed);
+    let num_rekeyed = rekey(db, &names.revisions, "id", "number").await?;
+    println!("Re-keyed {} revisions by their id", num_rekeyed);
+
+    for collection in [&entities, &revisions] {
+        let result = collection.update_many(
+            doc! {"inherited_class_ids": {"$exists": false}},
+            doc! {"$set": {"inherited_class_ids": []}},
+            options.clone()
+        ).await?;
+        println!("Added empty inherited classes to {} documents of {}", result.modified_count, collection.name());
+    }
+
+    let num_invalid_timestamps = revisions.count_documents(doc! {"timestamp": {"$type": "string"}}, None).await?;
+    let invalid_ops = doc! {"entity_diff": {"$elemMatch": {"op": {"$nin": PatchOp::NAMES.to_vec()}}}};
+    let num_invalid_ops = revisions.count_documents(invalid_ops.clone(), None).await?
+        + chunks.count_documents(invalid_ops, None).await?;
+
+    if num_invalid_timestamps > 0 || num_invalid_ops > 0 {
+        println!("{} revisions have timestamps that can't be parsed and {} documents have unknown op types. \
+                  Fix or delete them and run the migration again", num_invalid_timestamps, num_invalid_ops);
+        return Ok(false);
+    }
+
+    let invalid_documents = count_invalid_documents(db, names).await?.into_iter()
+        .filter(|(_, num_invalid)| *num_invalid > 0)
+        .collect::<Vec<(String, u64)>>();
+    if !invalid_documents.is_empty() {
+        for (collection_name, num_invalid) in invalid_documents {
+            println!("{} documents of {} don't match its validator", num_invalid, collection_name);
+        }
+        println!("Find them with {{$nor: [<validator>]}}, fix or delete them and run the migration again");
+        return Ok(false);
+    }
+
+    create_collections(db, names).await?;
+    println!("Collections migrated to the current schema");
+    Ok(true)
+}
+
+/// Replaces the documents whose `_id` isn't of the given type by copies keyed by their `key_field`, the
+/// `_id` of the documents written by the current version. `_id`s can't be updated, so each document is
+/// inserted again and the old one deleted. Documents already indexed again with the new key are kept.
+/// Returns the number of documents re-keyed.
+async fn rekey(db: &Database, collection_name: &str, key_field: &str, key_type: &str) -> Result<u64, Error> {
+    let collection = db.collection::<Document>(collection_name);
+    let filter = doc! {"_id": {"$not": {"$type": key_type}}, key_field: {"$type": key_type}};
+    let mut num_rekeyed = 0;
+    loop {
+        let options = FindOptions::builder().limit(REKEY_BATCH_SIZE).build();
+        let mut cursor = collection.find(filter.clone(), options).await?;
+        let mut old_ids = Vec::new();
+        let mut updates = Vec::new();
+        while cursor.advance().await? {
+            let mut document = cursor.deserialize_current()?;
+            let old_id = document.remove("_id").unwrap_or(Bson::Null);
+            let key = document.get(key_field).cloned().unwrap_or(Bson::Null);
+            old_ids.push(old_id);
+            // only inserted if no document has the new key yet
+            updates.push(Bson::Document(doc! {"q": {"_id": key}, "u": {"$setOnInsert": document}, "upsert": true}));
+        }
+        if old_ids.is_empty() {
+            return Ok(num_rekeyed);
+        }
+
+        let mut command = doc! {
+            "update": collection_name,
+            "updates": updates,
+            "ordered": false,
+            "bypassDocumentValidation": true
+        };
+        if let Some(write_concern) = db.write_concern() {
+            command.insert("writeConcern", to_bson(write_concern).expect("Error serializing write concern"));
+        }
+        let response = db.run_command(command, None).await?;
+        if let Ok(write_errors) = response.get_array("writeErrors") {
+            if let Some(Bson::Document(error)) = write_errors.first() {
+                println!("{} documents of {} could not be re-keyed, e.g. {}", write_errors.len(), collection_name, error);
+                return Ok(num_rekeyed);
+            }
+        }
+
+        num_rekeyed += old_ids.len() as u64;
+        collection.delete_many(doc! {"_id": {"$in": old_ids}}, None).await?;
+    }
+}
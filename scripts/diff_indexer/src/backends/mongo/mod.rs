@@ -0,0 +1,123 @@
+pub mod bulk;
+pub mod export;
+pub mod migrate;
+pub mod oversize;
+pub mod schema;
+
+use self::bulk::bulk_upsert;
+use self::oversize::OversizeHandler;
+use crate::backends::{BackendError, FailedDocument, RetryPolicy, StorageBackend};
+use crate::config::{CollectionNames, MongoConfig};
+use crate::model::{IndexedEntity, IndexedRevision, MongoRevisionChunk};
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::error::Error;
+use mongodb::{Client, Database};
+use serde_json::Value;
+
+/// Connects to the configured MongoDB server, checking that it is reachable
+pub async fn connect(config: &MongoConfig) -> Result<Database, Error> {
+    let client_options = config.get_client_options().await?;
+    let db_name = config.get_database(&client_options);
+    let client = Client::with_options(client_options)?;
+
+    // Ping the server to see if you can connect to the cluster
+    let db = client.database(&db_name);
+    db.run_command(doc! {"ping": 1}, None).await?;
+    Ok(db)
+}
+
+/// Stores entities and revisions in the entities, revisions and revision chunks collections, using bulk upserts
+pub struct MongoBackend {
+    db: Database,
+    names: CollectionNames,
+    oversize: OversizeHandler,
+    retry: RetryPolicy
+}
+
+impl MongoBackend {
+    pub fn new(db: Database, names: CollectionNames, oversize: OversizeHandler, retry: RetryPolicy) -> MongoBackend {
+        MongoBackend { db, names, oversize, retry }
+    }
+}
+
+#[async_trait]
+impl StorageBackend for MongoBackend {
+    async fn create_schema(&self) -> Result<(), BackendError> {
+        schema::create_collections(&self.db, &self.names).await?;
+        Ok(())
+    }
+
+    async fn create_indexes(&self) -> Result<(), BackendError> {
+        schema::create_indexes(&self.db, &self.names).await?;
+        Ok(())
+    }
+
+    async fn get_latest_revision_ids(&self, entity_ids: &[String]) -> Result<HashMap<String, u64>, BackendError> {
+        let pipeline = vec![
+            doc! {"$match": {"entity_id": {"$in": entity_ids}}},
+            doc! {"$group": {"_id": "$entity_id", "latest_revision_id": {"$max": "$id"}}}
+        ];
+        let mut cursor = self.db.collection::<Document>(&self.names.revisions).aggregate(pipeline, None).await?;
+
+        let mut latest_revision_ids = HashMap::new();
+        while cursor.advance().await? {
+            let result = cursor.deserialize_current()?;
+            let latest_revision_id = match result.get("latest_revision_id") {
+                Some(Bson::Int64(id)) => *id as u64,
+                Some(Bson::Int32(id)) => *id as u64,
+                _ => continue
+            };
+            latest_revision_ids.insert(result.get_str("_id")?.to_string(), latest_revision_id);
+        }
+        Ok(latest_revision_ids)
+    }
+
+    async fn write(&self, mut entities: Vec<IndexedEntity>, mut revisions: Vec<IndexedRevision>) -> Vec<FailedDocument> {
+        let mut chunks = Vec::<MongoRevisionChunk>::new();
+        let mut failed = self.oversize.handle_entities(&mut entities).await;
+        failed.append(&mut self.oversize.handle_revisions(&mut revisions, &mut chunks).await);
+
+        failed.append(&mut bulk_upsert(&self.db, &self.names.entities, &entities, &self.retry).await.failed);
+        failed.append(&mut bulk_upsert(&self.db, &self.names.revision_chunks, &chunks, &self.retry).await.failed);
+        failed.append(&mut self.delete_stale_chunks(&revisions).await);
+        failed.append(&mut bulk_upsert(&self.db, &self.names.revisions, &revisions, &self.retry).await.failed);
+        failed
+    }
+}
+
+impl MongoBackend {
+    /// Deletes the chunks left by a previous indexing of the oversize revisions, when it split them into
+    /// more chunks than now or they are now stored in GridFS
+    async fn delete_stale_chunks(&self, revisions: &[IndexedRevision]) -> Vec<FailedDocument> {
+        let oversize = revisions.iter()
+            .filter(|revision| revision.entity_diff_chunks.is_some() || revision.entity_diff_file.is_some())
+            .collect::<Vec<&IndexedRevision>>();
+        let stale_chunks = oversize.iter()
+            .map(|revision| doc! {
+                "revision_id": revision.id as i64,
+                "chunk_index": {"$gte": revision.entity_diff_chunks.unwrap_or(0)}
+            })
+            .collect::<Vec<Document>>();
+        if stale_chunks.is_empty() {
+            return Vec::new();
+        }
+
+        let chunks = self.db.collection::<Document>(&self.names.revision_chunks);
+        match chunks.delete_many(doc! {"$or": stale_chunks}, None).await {
+            Ok(_) => Vec::new(),
+            Err(e) => oversize.iter()
+                .map(|revision| FailedDocument {
+                    collection: self.names.revision_chunks.clone(),
+                    id: Value::from(revision.doc_id),
+                    code: None,
+                    error: format!("Error deleting the previous chunks of the revision: {}", e),
+                    document: None
+                })
+                .collect()
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use crate::config::CollectionNames;
+use crate::model::{IndexedEntity, IndexedOp, IndexedRevision, MongoRevisionChunk};
+use crate::backends::FailedDocument;
+
+use clap::ArgEnum;
+use mongodb::bson::{to_vec, Bson};
+use mongodb::error::Error;
+use mongodb::gridfs::GridFsBucket;
+use mongodb::options::GridFsBucketOptions;
+use mongodb::Database;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Maximum size of a BSON document stored by the server (16 MB), minus some room for the update
+/// statement the document is sent in
+const MAX_DOCUMENT_SIZE: usize = 16 * 1024 * 1024 - 16 * 1024;
+
+/// Maximum size of the ops stored in each revision chunk
+const MAX_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OversizeStrategy {
+    /// Store the entity_json or entity_diff of oversize documents in a GridFS bucket, referenced from the document
+    Gridfs,
+    /// Split the ops of oversize revisions into documents of the revision chunks collection
+    /// (oversize entities are stored in GridFS)
+    Split,
+    /// Do not index oversize documents, saving them to the dead letter file
+    Skip
+}
+
+/// Detects the documents over the BSON size limit and makes them fit following the chosen strategy
+pub struct OversizeHandler {
+    strategy: OversizeStrategy,
+    names: CollectionNames,
+    bucket: GridFsBucket
+}
+
+impl OversizeHandler {
+    pub fn new(db: &Database, strategy: OversizeStrategy, names: &CollectionNames) -> OversizeHandler {
+        let options = GridFsBucketOptions::builder().bucket_name(names.large_fields_bucket.clone()).build();
+        OversizeHandler { strategy, names: names.clone(), bucket: db.gridfs_bucket(options) }
+    }
+
+    /// Moves the JSON of oversize entities to GridFS, or removes the entities when they are skipped.
+    /// Returns the entities that could not be stored.
+    pub async fn handle_entities(&self, entities: &mut Vec<IndexedEntity>) -> Vec<FailedDocument> {
+        let mut failed = Vec::new();
+        let mut kept = Vec::with_capacity(entities.len());
+        for mut entity in entities.drain(..) {
+            let size = match get_size(&entity) {
+                Some(size) if size > MAX_DOCUMENT_SIZE => size,
+                _ => {
+                    kept.push(entity);
+                    continue;
+                }
+            };
+
+            println!("Entity {} takes {} bytes, over the maximum document size", entity.entity_id, size);
+            if self.strategy == OversizeStrategy::Skip {
+                failed.push(to_failed(&self.names.entities, &entity, Value::from(entity.doc_id.clone()), size));
+                continue;
+            }
+
+            let file_id = format!("{}/{}/entity_json", self.names.entities, entity.entity_id);
+            match self.store(&file_id, &entity.entity_json).await {
+                Ok(()) => {
+                    entity.entity_json = Value::Null;
+                    entity.entity_json_file = Some(file_id);
+                    kept.push(entity);
+                },
+                Err(e) => failed.push(FailedDocument {
+                    collection: self.names.entities.clone(),
+                    id: Value::from(entity.doc_id.clone()),
+                    code: None,
+                    error: format!("Error storing entity JSON in GridFS: {}", e),
+                    document: serde_json::to_value(&entity).ok()
+                })
+            }
+        }
+
+        *entities = kept;
+        failed
+    }
+
+    /// Moves the diff of oversize revisions to GridFS or to revision chunks, or removes the revisions when
+    /// they are skipped. Returns the revisions that could not be stored.
+    pub async fn handle_revisions(&self, revisions: &mut Vec<IndexedRevision>,
+                                  chunks: &mut Vec<MongoRevisionChunk>) -> Vec<FailedDocument> {
+        let mut failed = Vec::new();
+        let mut kept = Vec::with_capacity(revisions.len());
+        for mut revision in revisions.drain(..) {
+            let size = match get_size(&revision) {
+                Some(size) if size > MAX_DOCUMENT_SIZE => size,
+                _ => {
+                    kept.push(revision);
+                    continue;
+                }
+            };
+
+            println!("Revision {} of entity {} takes {} bytes, over the maximum document size",
+                     revision.id, revision.entity_id, size);
+            if self.strategy == OversizeStrategy::Skip {
+                failed.push(to_failed(&self.names.revisions, &revision, Value::from(revision.doc_id), size));
+                continue;
+            }
+
+            if self.strategy == OversizeStrategy::Split {
+                if let Some(ops_chunks) = split_ops(&revision.entity_diff) {
+                    revision.entity_diff_chunks = Some(ops_chunks.len() as u32);
+                    for (idx, entity_diff) in ops_chunks.into_iter().enumerate() {
+                        chunks.push(MongoRevisionChunk {
+                            doc_id: format!("{}_{}", revision.id, idx),
+                            revision_id: revision.id,
+                            entity_id: revision.entity_id.clone(),
+                            chunk_index: idx as u32,
+                            entity_diff
+                        });
+                    }
+                    revision.entity_diff = Vec::new();
+                    kept.push(revision);
+                    continue;
+                }
+                println!("Revision {} has an op over the maximum document size, storing it in GridFS", revision.id);
+            }
+
+            let file_id = format!("{}/{}/entity_diff", self.names.revisions, revision.id);
+            match self.store(&file_id, &revision.entity_diff).await {
+                Ok(()) => {
+                    revision.entity_diff = Vec::new();
+                    revision.entity_diff_file = Some(file_id);
+                    kept.push(revision);
+                },
+                Err(e) => failed.push(FailedDocument {
+                    collection: self.names.revisions.clone(),
+                    id: Value::from(revision.doc_id),
+                    code: None,
+                    error: format!("Error storing entity diff in GridFS: {}", e),
+                    document: serde_json::to_value(&revision).ok()
+                })
+            }
+        }
+
+        *revisions = kept;
+        failed
+    }
+
+    /// Stores a value as a JSON file with the given id, replacing the previous file so indexing stays idempotent
+    async fn store<T: Serialize>(&self, file_id: &str, value: &T) -> Result<(), Error> {
+        let contents = serde_json::to_vec(value).expect("Error serializing JSON");
+        // fails if there is no previous file. Any other error makes the upload fail with a duplicate key
+        let _ = self.bucket.delete(Bson::from(file_id)).await;
+
+        self.bucket.upload_from_futures_0_3_reader_with_id(Bson::from(file_id), file_id, contents.as_slice(), None).await
+    }
+}
+
+fn get_size<T: Serialize>(document: &T) -> Option<usize> {
+    to_vec(document).ok().map(|bytes| bytes.len())
+}
+
+fn to_failed<T: Serialize>(collection_name: &str, document: &T, id: Value, size: usize) -> FailedDocument {
+    FailedDocument {
+        collection: collection_name.to_string(),
+        id,
+        code: None,
+        error: format!("Document of {} bytes exceeds the maximum BSON document size", size),
+        document: serde_json::to_value(document).ok()
+    }
+}
+
+/// Splits the ops of a revision into consecutive chunks of at most `MAX_CHUNK_SIZE` bytes.
+/// Returns None if a single op doesn't fit in a document.
+fn split_ops(ops: &[IndexedOp]) -> Option<Vec<Vec<IndexedOp>>> {
+    let mut chunks = Vec::<Vec<IndexedOp>>::new();
+    let mut chunk = Vec::<IndexedOp>::new();
+    let mut chunk_size = 0;
+    for op in ops {
+        let op_size = get_size(op)?;
+        if op_size > MAX_DOCUMENT_SIZE {
+            return None;
+        }
+
+        if !chunk.is_empty() && chunk_size + op_size > MAX_CHUNK_SIZE {
+            chunks.push(std::mem::take(&mut chunk));
+            chunk_size = 0;
+        }
+        chunk_size += op_size;
+        chunk.push(op.clone());
+    }
+
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    Some(chunks)
+}
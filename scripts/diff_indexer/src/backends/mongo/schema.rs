@@ -0,0 +1,151 @@
+use crate::config::CollectionNames;
+
+use mongodb::bson::{doc, Document};
+use mongodb::error::Error;
+use mongodb::options::{CreateCollectionOptions, ValidationAction, ValidationLevel};
+use mongodb::{Database, IndexModel};
+use wd_history_core::model::PatchOp;
+
+/// A collection written by the indexer, along with its validator and the indexes needed by the queries
+struct CollectionSchema {
+    name: String,
+    validator: Document,
+    indexes: Vec<Document>
+}
+
+fn get_schemas(names: &CollectionNames) -> Vec<CollectionSchema> {
+    let string_array = doc! {"bsonType": "array", "items": {"bsonType": "string"}};
+    let integer = vec!["int", "long"];
+    let op = doc! {
+        "bsonType": "object",
+        "required": ["op", "path"],
+        "properties": {
+            "op": {"enum": PatchOp::NAMES.to_vec()},
+            "path": {"bsonType": "string"},
+            "from": {"bsonType": "string"},
+            "section": {"bsonType": "string"},
+            "property": {"bsonType": "string"},
+            "statement_index": {"bsonType": ["int", "long"]},
+            "statement_guid": {"bsonType": "string"},
+            "subpart": {"bsonType": "string"},
+            "language": {"bsonType": "string"},
+            "site": {"bsonType": "string"}
+        }
+    };
+
+    vec![
+        CollectionSchema {
+            name: names.entities.clone(),
+            validator: doc! {"$jsonSchema": {
+                "bsonType": "object",
+                "required": ["_id", "id", "entity_id", "class_ids", "inherited_class_ids", "entity_json"],
+                "properties": {
+                    "_id": {"bsonType": "string"},
+                    "id": {"bsonType": integer.clone()},
+                    "entity_id": {"bsonType": "string"},
+                    "class_ids": string_array.clone(),
+                    "inherited_class_ids": string_array.clone(),
+                    "entity_json": {"bsonType": ["object", "null"]},
+                    "entity_json_file": {"bsonType": "string"},
+                    "dump_version": {"bsonType": "string"}
+                }
+            }},
+            // descending keys, as created in the data exploration notebook
+            indexes: vec![doc! {"entity_id": -1}, doc! {"class_ids": -1}, doc! {"inherited_class_ids": 1}]
+        },
+        CollectionSchema {
+            name: names.revisions.clone(),
+            validator: doc! {"$jsonSchema": {
+                "bsonType": "object",
+                "required": ["_id", "id", "entity_id", "parent_id", "timestamp", "username", "comment",
+                             "class_ids", "inherited_class_ids", "entity_diff"],
+                "properties": {
+                    "_id": {"bsonType": integer.clone()},
+                    "id": {"bsonType": integer.clone()},
+                    "entity_id": {"bsonType": "string"},
+                    "parent_id": {"bsonType": integer.clone()},
+                    "timestamp": {"bsonType": "date"},
+                    "username": {"bsonType": "string"},
+                    "comment": {"bsonType": "string"},
+                    "class_ids": string_array.clone(),
+                    "inherited_class_ids": string_array,
+                    "entity_diff": {"bsonType": "array", "items": op.clone()},
+                    "entity_diff_file": {"bsonType": "string"},
+                    "entity_diff_chunks": {"bsonType": integer.clone()},
+                    "dump_version": {"bsonType": "string"}
+                }
+            }},
+            indexes: vec![doc! {"entity_id": -1}, doc! {"class_ids": -1}, doc! {"inherited_class_ids": 1},
+                          doc! {"timestamp": 1}, doc! {"username": 1}, doc! {"entity_diff.path": 1},
+                          doc! {"entity_diff.section": 1, "entity_diff.property": 1}, doc! {"entity_diff.statement_guid": 1},
+                          doc! {"entity_diff.language": 1}, doc! {"entity_diff.site": 1}, doc! {"dump_version": 1}]
+        },
+        CollectionSchema {
+            name: names.revision_chunks.clone(),
+            validator: doc! {"$jsonSchema": {
+                "bsonType": "object",
+                "required": ["_id", "revision_id", "entity_id", "chunk_index", "entity_diff"],
+                "properties": {
+                    "_id": {"bsonType": "string"},
+                    "revision_id": {"bsonType": integer.clone()},
+                    "entity_id": {"bsonType": "string"},
+                    "chunk_index": {"bsonType": integer},
+                    "entity_diff": {"bsonType": "array", "items": op}
+                }
+            }},
+            indexes: vec![doc! {"revision_id": 1, "chunk_index": 1}, doc! {"entity_diff.path": 1},
+                          doc! {"entity_diff.section": 1, "entity_diff.property": 1}]
+        }
+    ]
+}
+
+/// Creates the collections with their validators, or updates the validators of existing collections
+pub async fn create_collections(db: &Database, names: &CollectionNames) -> Result<(), Error> {
+    let existing = db.list_collection_names(None).await?;
+    for schema in get_schemas(names) {
+        if existing.contains(&schema.name) {
+            db.run_command(doc! {
+                "collMod": &schema.name,
+                "validator": schema.validator,
+                "validationLevel": "strict",
+                "validationAction": "error"
+            }, None).await?;
+        } else {
+            let options = CreateCollectionOptions::builder()
+                .validator(schema.validator)
+                .validation_level(ValidationLevel::Strict)
+                .validation_action(ValidationAction::Error)
+                .build();
+            db.create_collection(&schema.name, options).await?;
+            println!("Created collection {}", schema.name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Counts the documents of each collection that don't match its validator. Updating the validator of an
+/// existing collection doesn't check its documents, so they are counted before.
+pub async fn count_invalid_documents(db: &Database, names: &CollectionNames) -> Result<Vec<(String, u64)>, Error> {
+    let mut counts = Vec::new();
+    for schema in get_schemas(names) {
+        let num_invalid = db.collection::<Document>(&schema.name)
+            .count_documents(doc! {"$nor": [schema.validator]}, None).await?;
+        counts.push((schema.name, num_invalid));
+    }
+
+    Ok(counts)
+}
+
+/// Builds the indexes of every collection. Indexes that already exist are left untouched.
+pub async fn create_indexes(db: &Database, names: &CollectionNames) -> Result<(), Error> {
+    for schema in get_schemas(names) {
+        let indexes = schema.indexes.into_iter()
+            .map(|keys| IndexModel::builder().keys(keys).build())
+            .collect::<Vec<IndexModel>>();
+        println!("Building {} indexes of collection {}", indexes.len(), schema.name);
+        db.collection::<Document>(&schema.name).create_indexes(indexes, None).await?;
+    }
+
+    Ok(())
+}
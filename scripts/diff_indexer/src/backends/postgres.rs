@@ -0,0 +1,332 @@
+use crate::backends::{BackendError, FailedDocument, RetryPolicy, StorageBackend};
+use crate::config::PostgresConfig;
+use crate::model::{IndexedEntity, IndexedRevision};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use async_trait::async_trait;
+use native_tls::{Certificate, TlsConnector};
+use postgres_native_tls::MakeTlsConnector;
+use serde_json::Value;
+use tokio::sync::Mutex;
+use tokio_postgres::binary_copy::BinaryCopyInWriter;
+use tokio_postgres::types::{ToSql, Type};
+use tokio_postgres::config::SslMode;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::{Client, NoTls, Transaction};
+use wd_history_core::model::PatchOp;
+
+/// SQLSTATE codes of the errors worth retrying: failed concurrent transactions and servers that are
+/// restarting or out of connections
+const TRANSIENT_STATES: [SqlState; 6] = [SqlState::T_R_SERIALIZATION_FAILURE, SqlState::T_R_DEADLOCK_DETECTED,
+    SqlState::TOO_MANY_CONNECTIONS, SqlState::ADMIN_SHUTDOWN, SqlState::CRASH_SHUTDOWN, SqlState::CANNOT_CONNECT_NOW];
+
+/// Stores entities in the entities and entity_classes tables, and revisions in the revisions and ops
+/// tables, with one row per op of the entity diff. Batches are loaded with COPY into temporary staging
+/// tables and then merged into the tables.
+pub struct PostgresBackend {
+    config: tokio_postgres::Config,
+    /// Used when the connection string has sslmode=require
+    tls: Option<MakeTlsConnector>,
+    clients: Vec<Mutex<Client>>,
+    next_client: AtomicUsize,
+    retry: RetryPolicy
+}
+
+impl PostgresBackend {
+    /// Opens the given number of connections, which the writer tasks take in turns. Connections use TLS
+    /// when the connection string has sslmode=require, verifying the certificate of the server against the
+    /// system certificate authorities and the one of the config, if any.
+    pub async fn connect(config: &PostgresConfig, num_connections: usize,
+                         retry: RetryPolicy) -> Result<PostgresBackend, BackendError> {
+        let pg_config = config.get_url().parse::<tokio_postgres::Config>()?;
+        let tls = match pg_config.get_ssl_mode() {
+            SslMode::Require => Some(get_tls_connector(config.tls_ca_file.as_deref())?),
+            _ => None
+        };
+
+        let mut clients = Vec::new();
+        for _ in 0..num_connections.max(1) {
+            clients.push(Mutex::new(open_client(&pg_config, &tls).await?));
+        }
+        Ok(PostgresBackend { config: pg_config, tls, clients, next_client: AtomicUsize::new(0), retry })
+    }
+
+    fn get_client(&self) -> &Mutex<Client> {
+        &self.clients[self.next_client.fetch_add(1, Ordering::Relaxed) % self.clients.len()]
+    }
+
+    /// Writes a batch, retrying it after transient errors with a new connection if the previous one was lost
+    async fn write_with_retries(&self, client: &mut Client, entities: &[IndexedEntity],
+                                revisions: &[IndexedRevision]) -> Result<(), BackendError> {
+        let mut attempt = 0;
+        loop {
+            let error = match write_batch(client, entities, revisions).await {
+                Ok(()) => return Ok(()),
+                Err(e) => e
+            };
+            if !is_transient(&error) || attempt >= self.retry.max_retries {
+                return Err(error);
+            }
+
+            println!("Transient error writing {} entities and {} revisions, retrying: {}",
+                     entities.len(), revisions.len(), error);
+            tokio::time::sleep(self.retry.backoff(attempt)).await;
+            attempt += 1;
+            if client.is_closed() {
+                match open_client(&self.config, &self.tls).await {
+                    Ok(new_client) => *client = new_client,
+                    Err(e) => println!("Could not reconnect to PostgreSQL: {}", e)
+                }
+            }
+        }
+    }
+}
+
+async fn open_client(config: &tokio_postgres::Config,
+                     tls: &Option<MakeTlsConnector>) -> Result<Client, tokio_postgres::Error> {
+    let client = match tls {
+        Some(tls) => {
+            let (client, connection) = config.connect(tls.clone()).await?;
+            tokio::spawn(log_connection_error(connection));
+            client
+        },
+        None => {
+            let (client, connection) = config.connect(NoTls).await?;
+            tokio::spawn(log_connection_error(connection));
+            client
+        }
+    };
+    Ok(client)
+}
+
+async fn log_connection_error(connection: impl Future<Output=Result<(), tokio_postgres::Error>>) {
+    if let Err(e) = connection.await {
+        println!("PostgreSQL connection error: {}", e);
+    }
+}
+
+fn get_tls_connector(ca_file: Option<&str>) -> Result<MakeTlsConnector, BackendError> {
+    let mut builder = TlsConnector::builder();
+    if let Some(ca_file) = ca_file {
+        let pem = std::fs::read(ca_file).map_err(|e| format!("Could not read CA file {}: {}", ca_file, e))?;
+        builder.add_root_certificate(Certificate::from_pem(&pem)?);
+    }
+    Ok(MakeTlsConnector::new(builder.build()?))
+}
+
+/// Whether a failed write is worth retrying: lost connections, network errors and the errors of
+/// `TRANSIENT_STATES`
+fn is_transient(error: &BackendError) -> bool {
+    match error.downcast_ref::<tokio_postgres::Error>() {
+        Some(e) => e.is_closed() || e.code().is_some_and(|code| TRANSIENT_STATES.contains(code))
+            || e.source().is_some_and(|source| source.is::<std::io::Error>()),
+        None => false
+    }
+}
+
+fn get_schema() -> String {
+    let op_names = PatchOp::NAMES.iter().map(|name| format!("'{}'", name)).collect::<Vec<String>>().join(", ");
+    format!("
+        CREATE TABLE IF NOT EXISTS entities (
+            entity_id TEXT PRIMARY KEY,
+            id BIGINT NOT NULL,
+            entity_json JSONB NOT NULL,
+            dump_version TEXT
+        );
+        CREATE TABLE IF NOT EXISTS entity_classes (
+            entity_id TEXT NOT NULL,
+            class_id TEXT NOT NULL,
+            inherited BOOLEAN NOT NULL,
+            PRIMARY KEY (entity_id, class_id)
+        );
+        CREATE TABLE IF NOT EXISTS revisions (
+            id BIGINT PRIMARY KEY,
+            entity_id TEXT NOT NULL,
+            parent_id BIGINT NOT NULL,
+            timestamp TIMESTAMPTZ NOT NULL,
+            username TEXT NOT NULL,
+            comment TEXT NOT NULL,
+            dump_version TEXT
+        );
+        CREATE TABLE IF NOT EXISTS ops (
+            revision_id BIGINT NOT NULL,
+            op_index INTEGER NOT NULL,
+            op TEXT NOT NULL CHECK (op IN ({})),
+            path TEXT NOT NULL,
+            from_path TEXT,
+            value JSONB,
+            section TEXT,
+            property TEXT,
+            statement_index INTEGER,
+            statement_guid TEXT,
+            subpart TEXT,
+            language TEXT,
+            site TEXT,
+            PRIMARY KEY (revision_id, op_index)
+        );", op_names)
+}
+
+const INDEXES: &str = "
+    CREATE INDEX IF NOT EXISTS entity_classes_class_id ON entity_classes (class_id);
+    CREATE INDEX IF NOT EXISTS revisions_entity_id ON revisions (entity_id);
+    CREATE INDEX IF NOT EXISTS revisions_timestamp ON revisions (timestamp);
+    CREATE INDEX IF NOT EXISTS revisions_username ON revisions (username);
+    CREATE INDEX IF NOT EXISTS revisions_dump_version ON revisions (dump_version);
+    CREATE INDEX IF NOT EXISTS ops_path ON ops (path);
+    CREATE INDEX IF NOT EXISTS ops_section_property ON ops (section, property);
+    CREATE INDEX IF NOT EXISTS ops_statement_guid ON ops (statement_guid);";
+
+/// Staging tables, dropped when the transaction of the batch ends
+const STAGING_TABLES: &str = "
+    CREATE TEMP TABLE staging_entities (LIKE entities) ON COMMIT DROP;
+    CREATE TEMP TABLE staging_entity_classes (LIKE entity_classes) ON COMMIT DROP;
+    CREATE TEMP TABLE staging_revisions (LIKE revisions) ON COMMIT DROP;
+    CREATE TEMP TABLE staging_ops (LIKE ops) ON COMMIT DROP;";
+
+/// Upserts the staged rows. The classes and ops of the staged entities and revisions are replaced, so
+/// indexing the same entities again doesn't leave outdated rows.
+const MERGE_STAGING_TABLES: &str = "
+    INSERT INTO entities SELECT * FROM staging_entities
+        ON CONFLICT (entity_id) DO UPDATE SET id = EXCLUDED.id, entity_json = EXCLUDED.entity_json,
+            dump_version = EXCLUDED.dump_version;
+    DELETE FROM entity_classes WHERE entity_id IN (SELECT entity_id FROM staging_entities);
+    INSERT INTO entity_classes
+        SELECT DISTINCT ON (entity_id, class_id) * FROM staging_entity_classes ORDER BY entity_id, class_id, inherited;
+    INSERT INTO revisions SELECT * FROM staging_revisions
+        ON CONFLICT (id) DO UPDATE SET entity_id = EXCLUDED.entity_id, parent_id = EXCLUDED.parent_id,
+            timestamp = EXCLUDED.timestamp, username = EXCLUDED.username, comment = EXCLUDED.comment,
+            dump_version = EXCLUDED.dump_version;
+    DELETE FROM ops WHERE revision_id IN (SELECT id FROM staging_revisions);
+    INSERT INTO ops SELECT * FROM staging_ops;";
+
+/// Copies rows into a table with the binary COPY format
+async fn copy_rows(tx: &Transaction<'_>, table: &str, types: &[Type], rows: Vec<Vec<Box<dyn ToSql + Sync + Send>>>) -> Result<(), BackendError> {
+    let sink = tx.copy_in(&format!("COPY {} FROM STDIN BINARY", table)).await?;
+    let mut writer = pin!(BinaryCopyInWriter::new(sink, types));
+    for row in rows.iter() {
+        let values = row.iter().map(|value| value.as_ref() as &(dyn ToSql + Sync)).collect::<Vec<&(dyn ToSql + Sync)>>();
+        writer.as_mut().write(&values).await?;
+    }
+    writer.finish().await?;
+    Ok(())
+}
+
+async fn write_batch(client: &mut Client, entities: &[IndexedEntity], revisions: &[IndexedRevision]) -> Result<(), BackendError> {
+    let mut entity_rows = Vec::<Vec<Box<dyn ToSql + Sync + Send>>>::new();
+    let mut class_rows = Vec::<Vec<Box<dyn ToSql + Sync + Send>>>::new();
+    for entity in entities.iter() {
+        entity_rows.push(vec![Box::new(entity.entity_id.clone()), Box::new(entity.id as i64), Box::new(entity.entity_json.clone()),
+                              Box::new(entity.dump_version.clone())]);
+        for class_id in entity.class_ids.iter() {
+            class_rows.push(vec![Box::new(entity.entity_id.clone()), Box::new(class_id.clone()), Box::new(false)]);
+        }
+        for class_id in entity.inherited_class_ids.iter() {
+            class_rows.push(vec![Box::new(entity.entity_id.clone()), Box::new(class_id.clone()), Box::new(true)]);
+        }
+    }
+
+    let mut revision_rows = Vec::<Vec<Box<dyn ToSql + Sync + Send>>>::new();
+    let mut op_rows = Vec::<Vec<Box<dyn ToSql + Sync + Send>>>::new();
+    for revision in revisions.iter() {
+        revision_rows.push(vec![
+            Box::new(revision.id as i64), Box::new(revision.entity_id.clone()), Box::new(revision.parent_id as i64),
+            Box::new(revision.timestamp.to_system_time()), Box::new(revision.username.clone()), Box::new(revision.comment.clone()),
+            Box::new(revision.dump_version.clone())
+        ]);
+        for (op_index, op) in revision.entity_diff.iter().enumerate() {
+            let parsed = op.parsed_path.clone();
+            op_rows.push(vec![
+                Box::new(revision.id as i64), Box::new(op_index as i32), Box::new(op.op.name()), Box::new(op.path.clone()),
+                Box::new(op.from.clone()), Box::new(op.value.clone()), Box::new(parsed.section), Box::new(parsed.property),
+                Box::new(parsed.statement_index.map(|i| i as i32)), Box::new(parsed.statement_guid), Box::new(parsed.subpart),
+                Box::new(parsed.language), Box::new(parsed.site)
+            ]);
+        }
+    }
+
+    let tx = client.transaction().await?;
+    tx.batch_execute(STAGING_TABLES).await?;
+    copy_rows(&tx, "staging_entities", &[Type::TEXT, Type::INT8, Type::JSONB, Type::TEXT], entity_rows).await?;
+    copy_rows(&tx, "staging_entity_classes", &[Type::TEXT, Type::TEXT, Type::BOOL], class_rows).await?;
+    copy_rows(&tx, "staging_revisions", &[Type::INT8, Type::TEXT, Type::INT8, Type::TIMESTAMPTZ, Type::TEXT, Type::TEXT, Type::TEXT],
+        revision_rows).await?;
+    copy_rows(&tx, "staging_ops", &[Type::INT8, Type::INT4, Type::TEXT, Type::TEXT, Type::TEXT, Type::JSONB, Type::TEXT,
+        Type::TEXT, Type::INT4, Type::TEXT, Type::TEXT, Type::TEXT, Type::TEXT], op_rows).await?;
+    tx.batch_execute(MERGE_STAGING_TABLES).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+fn failed_entity(entity: &IndexedEntity, error: &BackendError) -> FailedDocument {
+    FailedDocument {
+        collection: "entities".to_string(),
+        id: Value::from(entity.entity_id.clone()),
+        code: None,
+        error: error.to_string(),
+        document: serde_json::to_value(entity).ok()
+    }
+}
+
+fn failed_revision(revision: &IndexedRevision, error: &BackendError) -> FailedDocument {
+    FailedDocument {
+        collection: "revisions".to_string(),
+        id: Value::from(revision.id),
+        code: None,
+        error: error.to_string(),
+        document: serde_json::to_value(revision).ok()
+    }
+}
+
+#[async_trait]
+impl StorageBackend for PostgresBackend {
+    async fn create_schema(&self) -> Result<(), BackendError> {
+        self.get_client().lock().await.batch_execute(&get_schema()).await?;
+        Ok(())
+    }
+
+    async fn create_indexes(&self) -> Result<(), BackendError> {
+        println!("Building {} indexes", INDEXES.matches("CREATE INDEX").count());
+        self.get_client().lock().await.batch_execute(INDEXES).await?;
+        Ok(())
+    }
+
+    async fn get_latest_revision_ids(&self, entity_ids: &[String]) -> Result<HashMap<String, u64>, BackendError> {
+        let rows = self.get_client().lock().await.query(
+            "SELECT entity_id, MAX(id) FROM revisions WHERE entity_id = ANY($1) GROUP BY entity_id", &[&entity_ids]).await?;
+        Ok(rows.iter().map(|row| (row.get::<_, String>(0), row.get::<_, i64>(1) as u64)).collect())
+    }
+
+    async fn write(&self, entities: Vec<IndexedEntity>, revisions: Vec<IndexedRevision>) -> Vec<FailedDocument> {
+        let mut client = self.get_client().lock().await;
+        let error = match self.write_with_retries(&mut client, &entities, &revisions).await {
+            Ok(()) => return Vec::new(),
+            Err(e) => e
+        };
+        if is_transient(&error) {
+            // the retries are exhausted, writing the documents one by one would fail as well
+            return entities.iter().map(|entity| failed_entity(entity, &error))
+                .chain(revisions.iter().map(|revision| failed_revision(revision, &error)))
+                .collect();
+        }
+
+        // a single row (e.g. with a \u0000 character, which JSONB rejects) makes the whole COPY fail,
+        // so the documents are written one by one to find the ones that can't be written
+        let mut failed = Vec::<FailedDocument>::new();
+        for entity in entities.iter() {
+            if let Err(e) = self.write_with_retries(&mut client, std::slice::from_ref(entity), &[]).await {
+                failed.push(failed_entity(entity, &e));
+            }
+        }
+        for revision in revisions.iter() {
+            if let Err(e) = self.write_with_retries(&mut client, &[], std::slice::from_ref(revision)).await {
+                failed.push(failed_revision(revision, &e));
+            }
+        }
+        failed
+    }
+}
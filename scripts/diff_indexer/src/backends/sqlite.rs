@@ -0,0 +1,218 @@
+use crate::backends::{BackendError, FailedDocument, StorageBackend};
+use crate::model::{IndexedEntity, IndexedRevision};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use rusqlite::{params, params_from_iter, Connection, Transaction};
+use serde_json::Value;
+use wd_history_core::model::PatchOp;
+
+/// Stores entities in the entities and entity_classes tables, and revisions in the revisions and ops
+/// tables, with one row per op of the entity diff
+pub struct SqliteBackend {
+    conn: Arc<Mutex<Connection>>
+}
+
+impl SqliteBackend {
+    pub fn open(path: &str) -> Result<SqliteBackend, BackendError> {
+        let conn = Connection::open(path)?;
+        // WAL lets the database be queried while it is being indexed
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        Ok(SqliteBackend { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    /// Runs a function with the connection in a blocking task, as rusqlite calls block
+    async fn with_connection<T, F>(&self, f: F) -> T
+        where T: Send + 'static, F: FnOnce(&mut Connection) -> T + Send + 'static
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || f(&mut conn.lock().expect("SQLite connection lock poisoned")))
+            .await
+            .expect("SQLite task failed")
+    }
+}
+
+fn get_schema() -> String {
+    let op_names = PatchOp::NAMES.iter().map(|name| format!("'{}'", name)).collect::<Vec<String>>().join(", ");
+    format!("
+        CREATE TABLE IF NOT EXISTS entities (
+            entity_id TEXT PRIMARY KEY,
+            id INTEGER NOT NULL,
+            entity_json TEXT NOT NULL,
+            dump_version TEXT
+        );
+        CREATE TABLE IF NOT EXISTS entity_classes (
+            entity_id TEXT NOT NULL,
+            class_id TEXT NOT NULL,
+            inherited INTEGER NOT NULL,
+            PRIMARY KEY (entity_id, class_id)
+        ) WITHOUT ROWID;
+        CREATE TABLE IF NOT EXISTS revisions (
+            id INTEGER PRIMARY KEY,
+            entity_id TEXT NOT NULL,
+            parent_id INTEGER NOT NULL,
+            timestamp TEXT NOT NULL,
+            username TEXT NOT NULL,
+            comment TEXT NOT NULL,
+            dump_version TEXT
+        );
+        CREATE TABLE IF NOT EXISTS ops (
+            revision_id INTEGER NOT NULL,
+            op_index INTEGER NOT NULL,
+            op TEXT NOT NULL CHECK (op IN ({})),
+            path TEXT NOT NULL,
+            from_path TEXT,
+            value TEXT,
+            section TEXT,
+            property TEXT,
+            statement_index INTEGER,
+            statement_guid TEXT,
+            subpart TEXT,
+            language TEXT,
+            site TEXT,
+            PRIMARY KEY (revision_id, op_index)
+        ) WITHOUT ROWID;", op_names)
+}
+
+const INDEXES: &str = "
+    CREATE INDEX IF NOT EXISTS entity_classes_class_id ON entity_classes (class_id);
+    CREATE INDEX IF NOT EXISTS revisions_entity_id ON revisions (entity_id);
+    CREATE INDEX IF NOT EXISTS revisions_timestamp ON revisions (timestamp);
+    CREATE INDEX IF NOT EXISTS revisions_username ON revisions (username);
+    CREATE INDEX IF NOT EXISTS revisions_dump_version ON revisions (dump_version);
+    CREATE INDEX IF NOT EXISTS ops_path ON ops (path);
+    CREATE INDEX IF NOT EXISTS ops_section_property ON ops (section, property);
+    CREATE INDEX IF NOT EXISTS ops_statement_guid ON ops (statement_guid);";
+
+fn write_entity(conn: &Connection, entity: &IndexedEntity) -> rusqlite::Result<()> {
+    conn.execute("INSERT OR REPLACE INTO entities (entity_id, id, entity_json, dump_version) VALUES (?1, ?2, ?3, ?4)",
+        params![entity.entity_id, entity.id as i64, entity.entity_json.to_string(), entity.dump_version])?;
+
+    // replace the classes, so the ones the entity no longer has are removed
+    conn.execute("DELETE FROM entity_classes WHERE entity_id = ?1", params![entity.entity_id])?;
+    let mut insert_class = conn.prepare_cached(
+        "INSERT OR IGNORE INTO entity_classes (entity_id, class_id, inherited) VALUES (?1, ?2, ?3)")?;
+    for class_id in entity.class_ids.iter() {
+        insert_class.execute(params![entity.entity_id, class_id, false])?;
+    }
+    for class_id in entity.inherited_class_ids.iter() {
+        insert_class.execute(params![entity.entity_id, class_id, true])?;
+    }
+    Ok(())
+}
+
+fn write_revision(conn: &Connection, revision: &IndexedRevision) -> Result<(), BackendError> {
+    conn.execute("INSERT OR REPLACE INTO revisions (id, entity_id, parent_id, timestamp, username, comment, dump_version) \
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![revision.id as i64, revision.entity_id, revision.parent_id as i64,
+                revision.timestamp.try_to_rfc3339_string()?, revision.username, revision.comment, revision.dump_version])?;
+
+    conn.execute("DELETE FROM ops WHERE revision_id = ?1", params![revision.id as i64])?;
+    let mut insert_op = conn.prepare_cached(
+        "INSERT INTO ops (revision_id, op_index, op, path, from_path, value, section, property, statement_index, \
+                          statement_guid, subpart, language, site) \
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)")?;
+    for (op_index, op) in revision.entity_diff.iter().enumerate() {
+        let parsed = &op.parsed_path;
+        insert_op.execute(params![
+            revision.id as i64, op_index as i64, op.op.name(), op.path, op.from,
+            op.value.as_ref().map(Value::to_string), parsed.section, parsed.property, parsed.statement_index,
+            parsed.statement_guid, parsed.subpart, parsed.language, parsed.site
+        ])?;
+    }
+    Ok(())
+}
+
+/// Writes a document inside a savepoint, so a document that fails doesn't leave part of its rows written
+fn write_document<T, F>(tx: &mut Transaction, table: &str, id: Value, document: &T, write: F) -> Option<FailedDocument>
+    where T: serde::Serialize, F: FnOnce(&Connection, &T) -> Result<(), BackendError>
+{
+    let result = tx.savepoint().map_err(BackendError::from).and_then(|savepoint| {
+        write(&savepoint, document)?;
+        savepoint.commit()?;
+        Ok(())
+    });
+
+    result.err().map(|e| FailedDocument {
+        collection: table.to_string(),
+        id,
+        code: None,
+        error: e.to_string(),
+        document: serde_json::to_value(document).ok()
+    })
+}
+
+fn write_batch(conn: &mut Connection, entities: &[IndexedEntity], revisions: &[IndexedRevision]) -> Vec<FailedDocument> {
+    let mut failed = Vec::<FailedDocument>::new();
+    let mut tx = match conn.transaction() {
+        Ok(tx) => tx,
+        Err(e) => return fail_all(entities, revisions, &e.to_string())
+    };
+
+    for entity in entities.iter() {
+        failed.extend(write_document(&mut tx, "entities", Value::from(entity.entity_id.clone()), entity,
+            |conn, entity| Ok(write_entity(conn, entity)?)));
+    }
+    for revision in revisions.iter() {
+        failed.extend(write_document(&mut tx, "revisions", Value::from(revision.id), revision, write_revision));
+    }
+
+    match tx.commit() {
+        Ok(()) => failed,
+        Err(e) => fail_all(entities, revisions, &e.to_string())
+    }
+}
+
+/// Marks every document of the batch as failed, when the transaction could not be started or committed
+fn fail_all(entities: &[IndexedEntity], revisions: &[IndexedRevision], error: &str) -> Vec<FailedDocument> {
+    let failed_entities = entities.iter().map(|entity| FailedDocument {
+        collection: "entities".to_string(),
+        id: Value::from(entity.entity_id.clone()),
+        code: None,
+        error: error.to_string(),
+        document: serde_json::to_value(entity).ok()
+    });
+    let failed_revisions = revisions.iter().map(|revision| FailedDocument {
+        collection: "revisions".to_string(),
+        id: Value::from(revision.id),
+        code: None,
+        error: error.to_string(),
+        document: serde_json::to_value(revision).ok()
+    });
+    failed_entities.chain(failed_revisions).collect()
+}
+
+#[async_trait]
+impl StorageBackend for SqliteBackend {
+    async fn create_schema(&self) -> Result<(), BackendError> {
+        self.with_connection(|conn| conn.execute_batch(&get_schema())).await?;
+        Ok(())
+    }
+
+    async fn create_indexes(&self) -> Result<(), BackendError> {
+        println!("Building {} indexes", INDEXES.matches("CREATE INDEX").count());
+        self.with_connection(|conn| conn.execute_batch(INDEXES)).await?;
+        Ok(())
+    }
+
+    async fn get_latest_revision_ids(&self, entity_ids: &[String]) -> Result<HashMap<String, u64>, BackendError> {
+        let entity_ids = entity_ids.to_vec();
+        let latest_revision_ids = self.with_connection(move |conn| {
+            let placeholders = vec!["?"; entity_ids.len()].join(", ");
+            let mut statement = conn.prepare(&format!(
+                "SELECT entity_id, MAX(id) FROM revisions WHERE entity_id IN ({}) GROUP BY entity_id", placeholders))?;
+            let rows = statement.query_map(params_from_iter(entity_ids.iter()), |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as u64))
+            })?;
+            rows.collect::<rusqlite::Result<HashMap<String, u64>>>()
+        }).await?;
+        Ok(latest_revision_ids)
+    }
+
+    async fn write(&self, entities: Vec<IndexedEntity>, revisions: Vec<IndexedRevision>) -> Vec<FailedDocument> {
+        self.with_connection(move |conn| write_batch(conn, &entities, &revisions)).await
+    }
+}
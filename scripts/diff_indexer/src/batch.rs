@@ -0,0 +1,240 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+
+use serde_json::value::RawValue;
+use wd_history_core::io::{get_dump_version, get_file_name};
+use wd_history_core::model::WikidataItem;
+use wd_history_core::shard::{read_raw_shard, ShardError};
+
+/// Entities written in a single bulk request, along with the input files they come from
+#[derive(Debug, Default)]
+pub struct Batch {
+    pub entities: Vec<WikidataItem>,
+    /// Dump version of each entity, taken from the name of its input file
+    pub dump_versions: Vec<Option<String>>,
+    /// Number of documents of the batch: an entity document plus a document per revision
+    pub num_documents: usize,
+    /// Size of the JSON of the entities in the input files
+    pub num_bytes: usize,
+    /// Part of the size of the input files taken by the entities, which is smaller than `num_bytes` for
+    /// compressed files. Used to report the progress.
+    pub num_file_bytes: usize,
+    /// Input files with entities in this batch
+    pub files: Vec<String>,
+    /// Input files whose last entity is in this batch, along with the number of batches with entities of each file
+    pub completed_files: Vec<(String, usize)>
+}
+
+/// Reads the entities of the input files in order and groups them into batches of at least
+/// `bulk_size` documents, or `bulk_bytes` bytes of JSON if set. Entities are never split between
+/// batches, and batches may contain entities of several files. Stops at the first file that can't be read,
+/// after the batch with the entities of the previous files.
+pub struct BatchReader {
+    files: VecDeque<PathBuf>,
+    bulk_size: usize,
+    bulk_bytes: Option<usize>,
+    dump_version: Option<String>,
+    current_file: Option<String>,
+    current_dump_version: Option<String>,
+    current_file_batches: usize,
+    /// Size of the current file divided by the size of the JSON of its entities
+    current_file_ratio: f64,
+    current_entities: VecDeque<Box<RawValue>>,
+    /// Error reading the last file, returned after the current batch
+    error: Option<ShardError>
+}
+
+impl BatchReader {
+    /// `dump_version` overrides the dump version of every file
+    pub fn new(files: Vec<PathBuf>, bulk_size: usize, bulk_bytes: Option<usize>, dump_version: Option<String>) -> BatchReader {
+        BatchReader { files: files.into(), bulk_size, bulk_bytes, dump_version, current_file: None,
+                      current_dump_version: None, current_file_batches: 0, current_file_ratio: 1.0,
+                      current_entities: VecDeque::new(), error: None }
+    }
+
+    /// Loads the next input file, returning false if there are no more files
+    fn next_file(&mut self) -> Result<bool, ShardError> {
+        let path = match self.files.pop_front() {
+            Some(path) => path,
+            None => return Ok(false)
+        };
+
+        let shard = read_raw_shard(&path)?;
+        let entities = shard.items;
+        let file_size = path.metadata().map(|m| m.len()).unwrap_or(0);
+        let json_size = entities.iter().map(|e| e.get().len()).sum::<usize>().max(1);
+        self.current_file = Some(get_file_name(&path));
+        self.current_dump_version = self.dump_version.clone()
+            .or(shard.header.dump_date)
+            .or_else(|| get_dump_version(&get_file_name(&path)));
+        self.current_file_batches = 0;
+        self.current_file_ratio = file_size as f64 / json_size as f64;
+        self.current_entities = entities.into();
+        Ok(true)
+    }
+
+    fn complete_file(&mut self, batch: &mut Batch) {
+        if let Some(file_name) = self.current_file.take() {
+            batch.completed_files.push((file_name, self.current_file_batches));
+        }
+    }
+
+    fn is_full(&self, batch: &Batch) -> bool {
+        batch.num_documents >= self.bulk_size || self.bulk_bytes.is_some_and(|bytes| batch.num_bytes >= bytes)
+    }
+}
+
+impl Iterator for BatchReader {
+    type Item = Result<Batch, ShardError>;
+
+    fn next(&mut self) -> Option<Result<Batch, ShardError>> {
+        let mut batch = Batch::default();
+        while !self.is_full(&batch) {
+            let raw_entity = match self.current_entities.pop_front() {
+                Some(raw_entity) => raw_entity,
+                None => {
+                    self.complete_file(&mut batch);
+                    match self.next_file() {
+                        Ok(true) => continue,
+                        Ok(false) => break,
+                        Err(e) => {
+                            self.files.clear();
+                            self.error = Some(e);
+                            break;
+                        }
+                    }
+                }
+            };
+
+            let file_name = self.current_file.as_ref().unwrap();
+            if batch.files.last() != Some(file_name) {
+                batch.files.push(file_name.clone());
+                self.current_file_batches += 1;
+            }
+
+            let entity: WikidataItem = serde_json::from_str(raw_entity.get())
+                .unwrap_or_else(|e| panic!("Error parsing entity of file {}: {}", file_name, e));
+            batch.num_bytes += raw_entity.get().len();
+            batch.num_file_bytes += (raw_entity.get().len() as f64 * self.current_file_ratio) as usize;
+            batch.num_documents += 1 + entity.revisions.len();
+            batch.entities.push(entity);
+            batch.dump_versions.push(self.current_dump_version.clone());
+        }
+
+        // the current file may be completed by the last entity of the batch
+        if self.current_entities.is_empty() {
+            self.complete_file(&mut batch);
+        }
+
+        if batch.files.is_empty() && batch.completed_files.is_empty() {
+            self.error.take().map(Err)
+        } else {
+            Some(Ok(batch))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::{json, Value};
+
+    /// Entity with the given number of revisions and a label of the given length
+    fn entity(number: u64, num_revisions: u64, label_length: usize) -> Value {
+        let revisions = (1..=num_revisions)
+            .map(|id| json!({"id": number * 100 + id, "parent_id": 0, "timestamp": "2021-11-01T00:00:00Z",
+                             "username": "", "comment": "", "entity_diff": null}))
+            .collect::<Vec<Value>>();
+        json!({"id": number, "entity_id": format!("Q{}", number),
+               "entity_json": {"labels": {"en": {"language": "en", "value": "x".repeat(label_length)}}},
+               "revisions": revisions})
+    }
+
+    /// Writes each list of entities to a diff file and returns their paths
+    fn write_files(dir_name: &str, files: &[Vec<Value>]) -> Vec<PathBuf> {
+        let dir = std::env::temp_dir().join(format!("diff_indexer_batch_test_{}_{}", dir_name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        files.iter().enumerate()
+            .map(|(i, entities)| {
+                let path = dir.join(format!("wikidatawiki-20211101-pages-meta-history1.xml-p1p154_{}.json", i));
+                std::fs::write(&path, Value::from(entities.clone()).to_string()).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    fn remove_files(paths: &[PathBuf]) {
+        std::fs::remove_dir_all(paths[0].parent().unwrap()).unwrap();
+    }
+
+    fn entity_ids(batch: &Batch) -> Vec<&str> {
+        batch.entities.iter().map(|e| e.entity_id.as_str()).collect()
+    }
+
+    #[test]
+    fn batches_of_bulk_size() {
+        // 2 documents per entity: the entity and its revision
+        let paths = write_files("size", &[
+            vec![entity(1, 1, 1), entity(2, 1, 1), entity(3, 1, 1)],
+            vec![entity(4, 1, 1), entity(5, 1, 1)]
+        ]);
+        let batches = BatchReader::new(paths.clone(), 4, None, None).collect::<Result<Vec<Batch>, _>>().unwrap();
+        assert_eq!(batches.iter().map(entity_ids).collect::<Vec<_>>(),
+                   vec![vec!["Q1", "Q2"], vec!["Q3", "Q4"], vec!["Q5"]]);
+        assert_eq!(batches.iter().map(|b| b.num_documents).collect::<Vec<_>>(), vec![4, 4, 2]);
+
+        // each file is completed by the batch with its last entity
+        assert_eq!(batches[0].files, vec![get_file_name(&paths[0])]);
+        assert!(batches[0].completed_files.is_empty());
+        assert_eq!(batches[1].files, vec![get_file_name(&paths[0]), get_file_name(&paths[1])]);
+        assert_eq!(batches[1].completed_files, vec![(get_file_name(&paths[0]), 2)]);
+        assert_eq!(batches[2].completed_files, vec![(get_file_name(&paths[1]), 2)]);
+        assert_eq!(batches[2].dump_versions, vec![Some("20211101".to_string())]);
+
+        // entities with many revisions are not split
+        let batches = BatchReader::new(paths.clone(), 1, None, Some("20220101".to_string()))
+            .collect::<Result<Vec<Batch>, _>>().unwrap();
+        assert_eq!(batches.len(), 5);
+        assert_eq!(batches[0].dump_versions, vec![Some("20220101".to_string())]);
+        remove_files(&paths);
+    }
+
+    #[test]
+    fn batches_of_bulk_bytes() {
+        let paths = write_files("bytes", &[(1..=5).map(|n| entity(n, 3, 100)).collect()]);
+        let entity_size = entity(1, 3, 100).to_string().len();
+        let batches = BatchReader::new(paths.clone(), 1000, Some(2 * entity_size), None)
+            .collect::<Result<Vec<Batch>, _>>().unwrap();
+        assert_eq!(batches.iter().map(entity_ids).collect::<Vec<_>>(),
+                   vec![vec!["Q1", "Q2"], vec!["Q3", "Q4"], vec!["Q5"]]);
+        assert_eq!(batches[0].num_bytes, 2 * entity_size);
+        remove_files(&paths);
+    }
+
+    #[test]
+    fn entity_larger_than_bulk_bytes() {
+        let paths = write_files("large", &[vec![entity(1, 1, 10_000), entity(2, 1, 10), entity(3, 1, 10)]]);
+        let small_size = entity(2, 1, 10).to_string().len();
+        let batches = BatchReader::new(paths.clone(), 1000, Some(2 * small_size), None)
+            .collect::<Result<Vec<Batch>, _>>().unwrap();
+        // the large entity gets a batch of its own instead of being dropped or split
+        assert_eq!(batches.iter().map(entity_ids).collect::<Vec<_>>(), vec![vec!["Q1"], vec!["Q2", "Q3"]]);
+        assert!(batches[0].num_bytes > 2 * small_size);
+        remove_files(&paths);
+    }
+
+    #[test]
+    fn error_after_previous_files() {
+        let mut paths = write_files("error", &[vec![entity(1, 1, 1)]]);
+        let invalid_path = paths[0].with_file_name("invalid_0.json");
+        std::fs::write(&invalid_path, "{").unwrap();
+        paths.push(invalid_path);
+
+        let mut reader = BatchReader::new(paths.clone(), 1000, None, None);
+        assert_eq!(entity_ids(&reader.next().unwrap().unwrap()), vec!["Q1"]);
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+        remove_files(&paths);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::backends::BackendError;
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// First line of the checkpoint file, followed by the backend and database the files were indexed into
+const TARGET_PREFIX: &str = "# target: ";
+
+/// Keeps track of the input files that have been fully indexed, so the indexing can be resumed after a
+/// crash. The name of each file is appended to the checkpoint file once all its documents are written.
+pub struct Checkpoint {
+    path: PathBuf,
+    indexed_files: HashSet<String>
+}
+
+impl Checkpoint {
+    /// Loads the checkpoint file of the indexing into the given target, a description of the backend and
+    /// database (see `get_backend`). Fails if the file was written while indexing into another target, whose
+    /// files are not indexed in this one.
+    pub fn load(path: impl AsRef<Path>, target: &str) -> Result<Checkpoint, BackendError> {
+        let path = path.as_ref().to_path_buf();
+        let mut indexed_files = HashSet::new();
+        let mut file_target = None;
+        if let Ok(file) = File::open(&path) {
+            for line in BufReader::new(file).lines() {
+                let line = line.unwrap_or_else(|e| panic!("Error reading checkpoint file {:?}: {}", &path, e));
+                if let Some(line_target) = line.strip_prefix(TARGET_PREFIX) {
+                    file_target = Some(line_target.trim().to_string());
+                } else if !line.trim().is_empty() {
+                    indexed_files.insert(line.trim().to_string());
+                }
+            }
+        }
+
+        match file_target {
+            Some(file_target) if file_target != target => {
+                return Err(format!("The checkpoint file {} belongs to the indexing into {}, not {}. Delete it to \
+                                    index every file again, or choose another one with --checkpoint-file",
+                                   path.display(), file_target, target).into());
+            },
+            Some(_) => {},
+            None => {
+                if !indexed_files.is_empty() {
+                    // written by a previous version, which didn't record the target
+                    println!("The checkpoint file {:?} does not say which database its files were indexed into, \
+                              assuming it is {}", path, target);
+                }
+                let mut file = File::create(&path)
+                    .unwrap_or_else(|e| panic!("Could not create checkpoint file {:?}: {}", &path, e));
+                writeln!(file, "{}{}", TARGET_PREFIX, target).expect("Error writing checkpoint file");
+                for file_name in indexed_files.iter() {
+                    writeln!(file, "{}", file_name).expect("Error writing checkpoint file");
+                }
+                file.sync_data().expect("Error writing checkpoint file");
+            }
+        }
+
+        Ok(Checkpoint { path, indexed_files })
+    }
+
+    pub fn num_indexed_files(&self) -> usize {
+        self.indexed_files.len()
+    }
+
+    pub fn is_indexed(&self, file_name: &str) -> bool {
+        self.indexed_files.contains(file_name)
+    }
+
+    /// Records that the given files have been fully indexed
+    pub fn mark_indexed(&mut self, file_names: &[String]) {
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)
+            .unwrap_or_else(|e| panic!("Could not open checkpoint file {:?}: {}", &self.path, e));
+        for file_name in file_names {
+            writeln!(file, "{}", file_name).expect("Error writing checkpoint file");
+            self.indexed_files.insert(file_name.clone());
+        }
+        file.sync_data().expect("Error writing checkpoint file");
+    }
+}
+
+/// Keeps track of the batches written for each input file. Batches are written concurrently, so a file
+/// is fully indexed once all its batches have been written, whatever their order.
+#[derive(Default)]
+pub struct FileTracker {
+    written_batches: HashMap<String, usize>,
+    total_batches: HashMap<String, usize>,
+    failed_files: HashSet<String>
+}
+
+impl FileTracker {
+    /// Records a written batch, given the files it has entities of and the files it completes along with
+    /// their number of batches. Returns the files that are now fully indexed.
+    pub fn add_batch(&mut self, files: &[String], completed_files: &[(String, usize)], ok: bool) -> Vec<String> {
+        for file_name in files {
+            if ok {
+                *self.written_batches.entry(file_name.clone()).or_default() += 1;
+            } else {
+                self.failed_files.insert(file_name.clone());
+            }
+        }
+        for (file_name, num_batches) in completed_files {
+            self.total_batches.insert(file_name.clone(), *num_batches);
+        }
+
+        let mut indexed_files = Vec::new();
+        for file_name in files.iter().chain(completed_files.iter().map(|(f, _)| f)) {
+            if self.failed_files.contains(file_name) {
+                continue;
+            }
+            let written = self.written_batches.get(file_name).copied().unwrap_or(0);
+            if self.total_batches.get(file_name) == Some(&written) && !indexed_files.contains(file_name) {
+                indexed_files.push(file_name.clone());
+            }
+        }
+
+        for file_name in indexed_files.iter() {
+            self.written_batches.remove(file_name);
+            self.total_batches.remove(file_name);
+        }
+        indexed_files
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn file_indexed_after_all_batches() {
+        let mut tracker = FileTracker::default();
+        assert!(tracker.add_batch(&files(&["a"]), &[], true).is_empty());
+        // the last batch of a completes it, while b still has batches to write
+        assert_eq!(tracker.add_batch(&files(&["a", "b"]), &[("a".to_string(), 2)], true), files(&["a"]));
+        assert!(tracker.add_batch(&files(&["b"]), &[], true).is_empty());
+        assert_eq!(tracker.add_batch(&files(&["b"]), &[("b".to_string(), 3)], true), files(&["b"]));
+    }
+
+    #[test]
+    fn file_indexed_after_batches_written_out_of_order() {
+        let mut tracker = FileTracker::default();
+        // the last batch is written before the first ones
+        assert!(tracker.add_batch(&files(&["a"]), &[("a".to_string(), 3)], true).is_empty());
+        assert!(tracker.add_batch(&files(&["a"]), &[], true).is_empty());
+        assert_eq!(tracker.add_batch(&files(&["a"]), &[], true), files(&["a"]));
+        // a file without entities is indexed once its parsing is done
+        assert_eq!(tracker.add_batch(&[], &[("empty".to_string(), 0)], true), files(&["empty"]));
+    }
+
+    #[test]
+    fn file_not_indexed_after_failed_batch() {
+        let mut tracker = FileTracker::default();
+        assert!(tracker.add_batch(&files(&["a", "b"]), &[("a".to_string(), 2)], false).is_empty());
+        assert!(tracker.add_batch(&files(&["a"]), &[], true).is_empty());
+        assert_eq!(tracker.add_batch(&files(&["b"]), &[("b".to_string(), 2)], true), Vec::<String>::new());
+    }
+
+    #[test]
+    fn checkpoint_of_target() {
+        let path = std::env::temp_dir().join(format!("diff_indexer_checkpoint_test_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut checkpoint = Checkpoint::load(&path, "sqlite /tmp/a.db").unwrap();
+        assert_eq!(checkpoint.num_indexed_files(), 0);
+        checkpoint.mark_indexed(&files(&["a_0.json", "a_1.json"]));
+        assert!(checkpoint.is_indexed("a_0.json"));
+
+        let checkpoint = Checkpoint::load(&path, "sqlite /tmp/a.db").unwrap();
+        assert_eq!(checkpoint.num_indexed_files(), 2);
+        assert!(checkpoint.is_indexed("a_1.json") && !checkpoint.is_indexed("a_2.json"));
+
+        // the files were not indexed into another database
+        let error = Checkpoint::load(&path, "sqlite /tmp/b.db").err().expect("Resuming into another target");
+        assert!(error.to_string().contains("sqlite /tmp/a.db"));
+
+        // files written by previous versions are assumed to belong to the current target
+        std::fs::write(&path, "a_0.json\n").unwrap();
+        let checkpoint = Checkpoint::load(&path, "sqlite /tmp/b.db").unwrap();
+        assert!(checkpoint.is_indexed("a_0.json"));
+        assert!(std::fs::read_to_string(&path).unwrap().starts_with("# target: sqlite /tmp/b.db\n"));
+        assert!(Checkpoint::load(&path, "sqlite /tmp/a.db").is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
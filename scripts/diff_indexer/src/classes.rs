@@ -0,0 +1,452 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::BufReader;
+use std::path::Path;
+
+use clap::ArgEnum;
+use serde::Deserialize;
+use wd_history_core::io::open_input_file;
+
+/// Maximum number of bad rows printed for each file, the rest are only counted
+const MAX_REPORTED_BAD_ROWS: usize = 10;
+
+/// Format of a file mapping entities to their classes, or classes to their superclasses
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum MappingFormat {
+    /// Detected from the file extension, ignoring .gz and .zst
+    Auto,
+    Csv,
+    Tsv,
+    /// SPARQL 1.1 query results in JSON, as downloaded from the Wikidata Query Service
+    SparqlJson
+}
+
+impl MappingFormat {
+    fn detect(path: &Path) -> MappingFormat {
+        let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_lowercase();
+        let file_name = file_name.trim_end_matches(".gz").trim_end_matches(".zst");
+        if file_name.ends_with(".tsv") {
+            MappingFormat::Tsv
+        } else if file_name.ends_with(".json") || file_name.ends_with(".srj") {
+            MappingFormat::SparqlJson
+        } else {
+            MappingFormat::Csv
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SparqlResults {
+    head: SparqlHead,
+    results: SparqlBindings
+}
+
+#[derive(Debug, Deserialize)]
+struct SparqlHead {
+    vars: Vec<String>
+}
+
+#[derive(Debug, Deserialize)]
+struct SparqlBindings {
+    bindings: Vec<HashMap<String, SparqlValue>>
+}
+
+#[derive(Debug, Deserialize)]
+struct SparqlValue {
+    value: String
+}
+
+/// Classes of the indexed entities and closure of the class hierarchy. Class ids are interned, and the
+/// classes of each entity are stored in a flat array sorted by entity, since there are millions of entities.
+#[derive(Debug, Default)]
+pub struct ClassMappings {
+    class_ids: Vec<String>,
+    class_index: HashMap<String, u32>,
+    /// Sorted keys of the entities (see `entity_key`)
+    entity_keys: Vec<u64>,
+    /// The classes of the entity `entity_keys[i]` are `entity_classes[entity_offsets[i]..entity_offsets[i + 1]]`
+    entity_offsets: Vec<u32>,
+    entity_classes: Vec<u32>,
+    /// Superclasses of each class, direct or inherited
+    superclasses: Vec<Vec<u32>>
+}
+
+impl ClassMappings {
+    /// Loads the classes of each entity, plus the class hierarchy closure if given
+    pub fn load(entities_classes_file: &str, format: MappingFormat, class_hierarchy_file: Option<&str>) -> ClassMappings {
+        let mut mappings = ClassMappings::default();
+
+        let mut pairs = Vec::<(u64, u32)>::new();
+        read_mapping_file(entities_classes_file, format, ["entity_id", "class_id"], |entity_id, class_id| {
+            let key = entity_key(entity_id).ok_or_else(|| format!("invalid entity id {:?}", entity_id))?;
+            validate_id(class_id)?;
+            pairs.push((key, mappings.intern(class_id)));
+            Ok(())
+        });
+        pairs.sort_unstable();
+        pairs.dedup();
+        for (key, class_idx) in pairs {
+            if mappings.entity_keys.last() != Some(&key) {
+                mappings.entity_keys.push(key);
+                mappings.entity_offsets.push(mappings.entity_classes.len() as u32);
+            }
+            mappings.entity_classes.push(class_idx);
+        }
+        mappings.entity_offsets.push(mappings.entity_classes.len() as u32);
+        println!("Loaded the classes of {} entities ({} distinct classes)", mappings.entity_keys.len(), mappings.class_ids.len());
+
+        if let Some(class_hierarchy_file) = class_hierarchy_file {
+            read_mapping_file(class_hierarchy_file, format_of(class_hierarchy_file), ["class_id", "superclass_id"],
+                              |class_id, superclass_id| {
+                validate_id(class_id)?;
+                validate_id(superclass_id)?;
+                let class_idx = mappings.intern(class_id);
+                let superclass_idx = mappings.intern(superclass_id);
+                let superclasses = &mut mappings.superclasses[class_idx as usize];
+                if !superclasses.contains(&superclass_idx) {
+                    superclasses.push(superclass_idx);
+                }
+                Ok(())
+            });
+        }
+
+        mappings.class_ids.shrink_to_fit();
+        mappings.superclasses.shrink_to_fit();
+        mappings
+    }
+
+    fn intern(&mut self, class_id: &str) -> u32 {
+        if let Some(idx) = self.class_index.get(class_id) {
+            return *idx;
+        }
+
+        let idx = self.class_ids.len() as u32;
+        self.class_ids.push(class_id.to_string());
+        self.class_index.insert(class_id.to_string(), idx);
+        self.superclasses.push(Vec::new());
+        idx
+    }
+
+    fn get_class_indices(&self, entity_id: &str) -> Option<&[u32]> {
+        let i = self.entity_keys.binary_search(&entity_key(entity_id)?).ok()?;
+        Some(&self.entity_classes[self.entity_offsets[i] as usize..self.entity_offsets[i + 1] as usize])
+    }
+
+    /// Returns the direct classes of an entity
+    pub fn get_class_ids(&self, entity_id: &str) -> Option<Vec<String>> {
+        self.get_class_indices(entity_id)
+            .map(|classes| classes.iter().map(|idx| self.class_ids[*idx as usize].clone()).collect())
+    }
+
+    /// Returns the superclasses of the classes of an entity which are not direct classes themselves
+    pub fn get_inherited_class_ids(&self, entity_id: &str) -> Vec<String> {
+        let classes = self.get_class_indices(entity_id).unwrap_or_default();
+        let inherited_class_ids = classes.iter()
+            .flat_map(|idx| self.superclasses[*idx as usize].iter())
+            .filter(|superclass_idx| !classes.contains(superclass_idx))
+            .map(|superclass_idx| self.class_ids[*superclass_idx as usize].as_str())
+            .collect::<BTreeSet<&str>>();
+
+        inherited_class_ids.into_iter().map(|class_id| class_id.to_string()).collect()
+    }
+}
+
+fn format_of(file: &str) -> MappingFormat {
+    MappingFormat::detect(Path::new(file))
+}
+
+/// Packs an entity id (Q42, P31, L7...) into an integer, with the letter in the highest byte
+fn entity_key(entity_id: &str) -> Option<u64> {
+    let letter = *entity_id.as_bytes().first()?;
+    if !letter.is_ascii_uppercase() || !entity_id[1..].bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let number = entity_id[1..].parse::<u64>().ok().filter(|number| *number < 1 << 56)?;
+    Some((letter as u64) << 56 | number)
+}
+
+fn validate_id(id: &str) -> Result<(), String> {
+    match entity_key(id) {
+        Some(_) => Ok(()),
+        None => Err(format!("invalid class id {:?}", id))
+    }
+}
+
+/// Removes the URI or prefix of an id from query results: `<http://www.wikidata.org/entity/Q5>`,
+/// `http://www.wikidata.org/entity/Q5` and `wd:Q5` all become `Q5`
+fn strip_prefix(value: &str) -> &str {
+    let value = value.trim().trim_start_matches('<').trim_end_matches('>');
+    let value = value.rsplit('/').next().unwrap_or(value);
+    value.rsplit(':').next().unwrap_or(value)
+}
+
+/// Whether the header of a file is a row of ids, i.e. the file has no header
+fn is_id_row(headers: &[String]) -> bool {
+    headers.len() >= 2 && headers[..2].iter().all(|header| entity_key(strip_prefix(header)).is_some())
+}
+
+/// Finds the columns with the given names (ignoring the `?` of SPARQL variables), or takes the first two
+fn find_columns(file: &str, headers: &[String], names: [&str; 2]) -> [usize; 2] {
+    let find = |name: &str| headers.iter().position(|header| header.trim().trim_start_matches('?').eq_ignore_ascii_case(name));
+    match (find(names[0]), find(names[1])) {
+        (Some(first), Some(second)) => [first, second],
+        _ => {
+            if headers.len() < 2 {
+                panic!("{} must have at least two columns, found {:?}", file, headers);
+            }
+            if is_id_row(headers) {
+                println!("{} has no header, using its first two columns as {} and {}", file, names[0], names[1]);
+            } else {
+                println!("{} has no {} and {} columns, using {} and {}",
+                         file, names[0], names[1], headers[0], headers[1]);
+            }
+            [0, 1]
+        }
+    }
+}
+
+/// Reads the pairs of ids of a mapping file, stripping their URI prefixes, and calls `add` with each of them.
+/// Rows that can't be read or that `add` rejects are reported and skipped. Returns the number of rows skipped.
+fn read_mapping_file<F>(file: &str, format: MappingFormat, names: [&str; 2], mut add: F) -> usize
+    where F: FnMut(&str, &str) -> Result<(), String> {
+    let format = match format {
+        MappingFormat::Auto => format_of(file),
+        format => format
+    };
+    let reader = BufReader::new(open_input_file(Path::new(file)));
+
+    let mut num_rows = 0;
+    let mut num_bad_rows = 0;
+    let mut handle_row = |row: usize, result: Result<(&str, &str), String>| {
+        num_rows += 1;
+        if let Err(error) = result.and_then(|(first, second)| add(strip_prefix(first), strip_prefix(second))) {
+            num_bad_rows += 1;
+            if num_bad_rows <= MAX_REPORTED_BAD_ROWS {
+                println!("Skipping row {} of {}: {}", row, file, error);
+            }
+        }
+    };
+
+    match format {
+        MappingFormat::SparqlJson => {
+            let results: SparqlResults = serde_json::from_reader(reader)
+                .unwrap_or_else(|e| panic!("Error parsing SPARQL results {}: {}", file, e));
+            let [first, second] = find_columns(file, &results.head.vars, names);
+            let (first, second) = (&results.head.vars[first], &results.head.vars[second]);
+            for (i, binding) in results.results.bindings.iter().enumerate() {
+                let result = match (binding.get(first), binding.get(second)) {
+                    (Some(first), Some(second)) => Ok((first.value.as_str(), second.value.as_str())),
+                    _ => Err("missing value".to_string())
+                };
+                handle_row(i + 1, result);
+            }
+        },
+        _ => {
+            // SPARQL TSV results don't quote values, and their literals contain quotes
+            let mut rdr = csv::ReaderBuilder::new()
+                .delimiter(if format == MappingFormat::Tsv { b'\t' } else { b',' })
+                .quoting(format != MappingFormat::Tsv)
+                .flexible(true)
+                .from_reader(reader);
+            let headers = rdr.headers().unwrap_or_else(|e| panic!("Error reading the header of {}: {}", file, e))
+                .iter().map(|header| header.to_string()).collect::<Vec<String>>();
+            let [first, second] = find_columns(file, &headers, names);
+            if is_id_row(&headers) {
+                handle_row(1, Ok((&headers[0], &headers[1])));
+            }
+            for result in rdr.records() {
+                match result {
+                    Ok(record) => {
+                        let row = record.position().map_or(0, |position| position.line() as usize);
+                        let result = match (record.get(first), record.get(second)) {
+                            (Some(first), Some(second)) => Ok((first, second)),
+                            _ => Err(format!("expected at least {} columns, found {}", first.max(second) + 1, record.len()))
+                        };
+                        handle_row(row, result);
+                    },
+                    Err(e) => {
+                        let row = e.position().map_or(0, |position| position.line() as usize);
+                        handle_row(row, Err(e.to_string()));
+                    }
+                }
+            }
+        }
+    }
+
+    if num_bad_rows > 0 {
+        println!("Skipped {} bad rows out of {} in {}", num_bad_rows, num_rows, file);
+    }
+    num_bad_rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use flate2::write::GzEncoder;
+
+    fn work_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("diff_indexer_classes_{}_test_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_file(dir: &Path, file_name: &str, contents: &[u8]) -> String {
+        let path = dir.join(file_name);
+        std::fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    /// Reads the pairs of a mapping file, along with the number of bad rows
+    fn read_pairs(file: &str, format: MappingFormat) -> (Vec<(String, String)>, usize) {
+        let mut pairs = Vec::new();
+        let num_bad_rows = read_mapping_file(file, format, ["entity_id", "class_id"], |entity_id, class_id| {
+            validate_id(entity_id)?;
+            validate_id(class_id)?;
+            pairs.push((entity_id.to_string(), class_id.to_string()));
+            Ok(())
+        });
+        (pairs, num_bad_rows)
+    }
+
+    fn pairs(ids: &[(&str, &str)]) -> Vec<(String, String)> {
+        ids.iter().map(|(first, second)| (first.to_string(), second.to_string())).collect()
+    }
+
+    #[test]
+    fn strip_prefixes() {
+        for value in ["Q5", " Q5 ", "wd:Q5", "http://www.wikidata.org/entity/Q5"] {
+            assert_eq!(strip_prefix(value), "Q5");
+        }
+        assert_eq!(strip_prefix("<http://www.wikidata.org/entity/Q5>"), "Q5");
+    }
+
+    #[test]
+    fn entity_keys() {
+        assert_eq!(entity_key("Q42"), Some((b'Q' as u64) << 56 | 42));
+        assert_ne!(entity_key("P31"), entity_key("Q31"));
+        assert!(entity_key("L7").is_some());
+        for entity_id in ["", "Q", "q42", "Q-1", "Q4a", "Q42 ", "Q72057594037927936"] {
+            assert_eq!(entity_key(entity_id), None, "{:?} should be invalid", entity_id);
+        }
+    }
+
+    #[test]
+    fn detect_formats() {
+        assert_eq!(MappingFormat::detect(Path::new("classes.csv")), MappingFormat::Csv);
+        assert_eq!(MappingFormat::detect(Path::new("classes.TSV.gz")), MappingFormat::Tsv);
+        assert_eq!(MappingFormat::detect(Path::new("results.srj.zst")), MappingFormat::SparqlJson);
+        assert_eq!(MappingFormat::detect(Path::new("classes")), MappingFormat::Csv);
+    }
+
+    #[test]
+    fn find_named_columns() {
+        let headers = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<String>>();
+        let names = ["entity_id", "class_id"];
+        assert_eq!(find_columns("f", &headers(&["label", "?Class_ID", " entity_id "]), names), [2, 1]);
+        assert_eq!(find_columns("f", &headers(&["item", "class", "label"]), names), [0, 1]);
+        assert!(is_id_row(&headers(&["wd:Q42", "<http://www.wikidata.org/entity/Q5>"])));
+        assert!(!is_id_row(&headers(&["item", "class"])));
+    }
+
+    #[test]
+    #[should_panic(expected = "must have at least two columns")]
+    fn find_columns_of_single_column() {
+        find_columns("f", &["entity_id".to_string()], ["entity_id", "class_id"]);
+    }
+
+    #[test]
+    fn csv_columns() {
+        let dir = work_dir("csv_columns");
+        // columns found by name, in any order and case
+        let file = write_file(&dir, "named.csv",
+                              b"label,class_id,Entity_ID\nDouglas Adams,Q5,Q42\n\"Earth, planet\",Q3504248,Q2\n");
+        assert_eq!(read_pairs(&file, MappingFormat::Auto), (pairs(&[("Q42", "Q5"), ("Q2", "Q3504248")]), 0));
+
+        // otherwise the first two columns are used, and the first row is data if it has ids
+        let file = write_file(&dir, "positional.csv", b"item,class\nQ42,Q5\n");
+        assert_eq!(read_pairs(&file, MappingFormat::Auto), (pairs(&[("Q42", "Q5")]), 0));
+        let file = write_file(&dir, "no_header.csv", b"Q42,Q5\nQ2,Q3504248\n");
+        assert_eq!(read_pairs(&file, MappingFormat::Auto), (pairs(&[("Q42", "Q5"), ("Q2", "Q3504248")]), 0));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tsv_query_results() {
+        let dir = work_dir("tsv_query_results");
+        // as downloaded from the Wikidata Query Service, with SPARQL variables and full entity URIs
+        let file = write_file(&dir, "results.tsv", b"?entity_id\t?class_id\t?label\n\
+            <http://www.wikidata.org/entity/Q42>\t<http://www.wikidata.org/entity/Q5>\t\"Douglas \"Adams\"\"@en\n");
+        assert_eq!(read_pairs(&file, MappingFormat::Auto), (pairs(&[("Q42", "Q5")]), 0));
+        // the format can be given for other extensions
+        let file = write_file(&dir, "results.txt", b"entity_id\tclass_id\nwd:Q42\twd:Q5\n");
+        assert_eq!(read_pairs(&file, MappingFormat::Tsv), (pairs(&[("Q42", "Q5")]), 0));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sparql_json_results() {
+        let dir = work_dir("sparql_json_results");
+        let file = write_file(&dir, "results.json", br#"{
+            "head": {"vars": ["class_id", "entity_id"]},
+            "results": {"bindings": [
+                {"entity_id": {"type": "uri", "value": "http://www.wikidata.org/entity/Q42"},
+                 "class_id": {"type": "uri", "value": "http://www.wikidata.org/entity/Q5"}},
+                {"entity_id": {"type": "uri", "value": "http://www.wikidata.org/entity/Q2"}}
+            ]}
+        }"#);
+        assert_eq!(read_pairs(&file, MappingFormat::Auto), (pairs(&[("Q42", "Q5")]), 1));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn compressed_files() {
+        let dir = work_dir("compressed_files");
+        let contents = b"entity_id,class_id\nQ42,Q5\n";
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(contents).unwrap();
+        let file = write_file(&dir, "classes.csv.gz", &encoder.finish().unwrap());
+        assert_eq!(read_pairs(&file, MappingFormat::Auto), (pairs(&[("Q42", "Q5")]), 0));
+
+        let compressed = zstd::encode_all(&b"entity_id\tclass_id\nQ42\tQ5\n"[..], 0).unwrap();
+        let file = write_file(&dir, "classes.tsv.zst", &compressed);
+        assert_eq!(MappingFormat::detect(Path::new(&file)), MappingFormat::Tsv);
+        assert_eq!(read_pairs(&file, MappingFormat::Auto), (pairs(&[("Q42", "Q5")]), 0));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn bad_rows() {
+        let dir = work_dir("bad_rows");
+        // a missing column, an invalid entity id and an invalid class id
+        let file = write_file(&dir, "bad.csv", b"entity_id,class_id\nQ42,Q5\nQ1\nfoo,Q5\nQ2,5\n");
+        let (read, num_bad_rows) = read_pairs(&file, MappingFormat::Csv);
+        assert_eq!(read, pairs(&[("Q42", "Q5")]));
+        assert_eq!(num_bad_rows, 3);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn load_mappings() {
+        let dir = work_dir("load_mappings");
+        let entities_classes_file = write_file(&dir, "entities_classes.csv",
+                                               b"entity_id,class_id\nQ42,Q5\nQ42,Q36180\nQ42,Q5\nQ1,Q2\n");
+        let class_hierarchy_file = write_file(&dir, "closure.csv", b"class_id,superclass_id,distance\n\
+            Q5,Q215627,1\nQ36180,Q5,1\nQ36180,Q215627,2\n");
+        let mappings = ClassMappings::load(&entities_classes_file, MappingFormat::Auto, Some(&class_hierarchy_file));
+        assert_eq!(mappings.get_class_ids("Q42"), Some(vec!["Q5".to_string(), "Q36180".to_string()]));
+        // Q5 is a direct class of Q42 too
+        assert_eq!(mappings.get_inherited_class_ids("Q42"), vec!["Q215627".to_string()]);
+        assert_eq!(mappings.get_class_ids("Q43"), None);
+        assert!(mappings.get_inherited_class_ids("Q1").is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
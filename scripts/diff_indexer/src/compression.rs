@@ -0,0 +1,71 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use clap::ArgEnum;
+use flate2::write::GzEncoder;
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd
+}
+
+impl Compression {
+    /// Extension added to the names of the files compressed with this method
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst"
+        }
+    }
+}
+
+/// An output file compressed with one of the methods
+pub enum OutputFile {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>)
+}
+
+impl OutputFile {
+    /// Creates an output file compressed with the given method
+    pub fn create(path: &Path, compression: Compression) -> io::Result<OutputFile> {
+        let writer = BufWriter::new(File::create(path)?);
+        Ok(match compression {
+            Compression::None => OutputFile::Plain(writer),
+            Compression::Gzip => OutputFile::Gzip(GzEncoder::new(writer, flate2::Compression::default())),
+            Compression::Zstd => OutputFile::Zstd(zstd::Encoder::new(writer, 0)?)
+        })
+    }
+
+    /// Writes the end of the compressed stream and flushes the file. Must be called once everything is
+    /// written: dropping the file instead would lose the errors of the last writes.
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            OutputFile::Plain(mut writer) => writer.flush(),
+            OutputFile::Gzip(encoder) => encoder.finish()?.flush(),
+            OutputFile::Zstd(encoder) => encoder.finish()?.flush()
+        }
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputFile::Plain(writer) => writer.write(buf),
+            OutputFile::Gzip(encoder) => encoder.write(buf),
+            OutputFile::Zstd(encoder) => encoder.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputFile::Plain(writer) => writer.flush(),
+            OutputFile::Gzip(encoder) => encoder.flush(),
+            OutputFile::Zstd(encoder) => encoder.flush()
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use crate::backends::FailedDocument;
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// JSON Lines file where the documents that could not be indexed are saved, one per line, along with
+/// the error returned by the server. The file is only created if some document fails.
+pub struct DeadLetterFile {
+    path: PathBuf,
+    writer: Option<BufWriter<File>>,
+    num_documents: usize
+}
+
+impl DeadLetterFile {
+    pub fn new(path: impl AsRef<Path>) -> DeadLetterFile {
+        DeadLetterFile { path: path.as_ref().to_path_buf(), writer: None, num_documents: 0 }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn num_documents(&self) -> usize {
+        self.num_documents
+    }
+
+    pub fn write(&mut self, documents: &[FailedDocument]) {
+        if documents.is_empty() {
+            return;
+        }
+
+        let path = &self.path;
+        let writer = self.writer.get_or_insert_with(|| {
+            let file = OpenOptions::new().create(true).append(true).open(path)
+                .unwrap_or_else(|e| panic!("Could not open dead letter file {:?}: {}", path, e));
+            BufWriter::new(file)
+        });
+        for document in documents {
+            serde_json::to_writer(&mut *writer, document).expect("Error writing dead letter file");
+            writeln!(writer).expect("Error writing dead letter file");
+        }
+        writer.flush().expect("Error writing dead letter file");
+        self.num_documents += documents.len();
+    }
+}
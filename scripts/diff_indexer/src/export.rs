@@ -0,0 +1,67 @@
+use crate::compression::{Compression, OutputFile};
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use wd_history_core::model::WikidataItem;
+use wd_history_core::shard::{write_header_line, ShardHeader};
+
+/// Writes exported entities as JSON Lines files of at most `entities_per_file` entities, named
+/// `<prefix>_00000.jsonl`, `<prefix>_00001.jsonl`... plus the extension of the compression method.
+/// Each file starts with the given header.
+pub struct ShardWriter {
+    output_dir: PathBuf,
+    header: ShardHeader,
+    prefix: String,
+    compression: Compression,
+    entities_per_file: usize,
+    num_files: usize,
+    num_file_entities: usize,
+    writer: Option<OutputFile>
+}
+
+impl ShardWriter {
+    pub fn new(output_dir: &str, header: ShardHeader, prefix: &str, compression: Compression,
+               entities_per_file: usize) -> ShardWriter {
+        std::fs::create_dir_all(output_dir).unwrap_or_else(|e| panic!("Could not create folder {}: {}", output_dir, e));
+        ShardWriter {
+            output_dir: PathBuf::from(output_dir),
+            header,
+            prefix: prefix.to_string(),
+            compression,
+            entities_per_file: entities_per_file.max(1),
+            num_files: 0,
+            num_file_entities: 0,
+            writer: None
+        }
+    }
+
+    /// Writes an entity, starting a new file if the current one is full
+    pub fn write(&mut self, item: &WikidataItem) -> io::Result<()> {
+        if self.writer.is_none() || self.num_file_entities >= self.entities_per_file {
+            if let Some(writer) = self.writer.take() {
+                writer.finish()?;
+            }
+            let file_name = format!("{}_{:05}.jsonl{}", self.prefix, self.num_files, self.compression.extension());
+            let mut writer = OutputFile::create(&self.output_dir.join(file_name), self.compression)?;
+            write_header_line(&mut writer, &self.header)?;
+            self.writer = Some(writer);
+            self.num_files += 1;
+            self.num_file_entities = 0;
+        }
+
+        let writer = self.writer.as_mut().unwrap();
+        serde_json::to_writer(&mut *writer, item)?;
+        writer.write_all(b"\n")?;
+        self.num_file_entities += 1;
+        Ok(())
+    }
+
+    /// Finishes the last file, returning the number of files written
+    pub fn finish(mut self) -> io::Result<usize> {
+        if let Some(writer) = self.writer.take() {
+            writer.finish()?;
+        }
+        Ok(self.num_files)
+    }
+}
@@ -1,221 +1,49 @@
-mod model;
+use diff_indexer::{run_export, run_index, run_migrate, BackendError, ExportArgs, IndexArgs};
+use diff_indexer::config::Config;
 
-use crate::model::{CSVRecord, MongoEntity, MongoRevision, MongoOp, WikidataItem};
+use clap::{Parser, Subcommand};
 
-use std::collections::HashMap;
-use std::fs::{DirEntry, File, read_dir};
-use std::io::BufReader;
 
-use core::clone::Clone;
-use clap::Parser;
-use indicatif::{ProgressBar, ProgressStyle};
-use mongodb::{bson::doc, options::ClientOptions, Client, Collection};
-use mongodb::error::Error;
-use serde_json::Value;
-
-
-/// Indexes wikidata diff files into a MongoDB instance
+/// Indexes wikidata diff files into MongoDB, PostgreSQL, SQLite or Elasticsearch, and exports them back to files
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    /// Input directory where the diff files are stored
-    #[clap(short, long)]
-    input_dir: String,
-    
-    /// File containing mappings from entities ids to their class id
-    #[clap(short, long)]
-    entities_classes_file: String,
-
-    /// Number of documents to index in each bulk request
-    #[clap(short, long, default_value_t=100)]
-    bulk_size: usize
-}
-
-async fn create_client() -> Result<Client, Error> {
-    let username = std::env::var("MONGO_USERNAME").unwrap_or_else(|_| "user".into());
-    let password = std::env::var("MONGO_PASSWORD").unwrap_or_else(|_| "".into());
-    let mongo_url = std::env::var("MONGO_URL").unwrap_or_else(|_| "localhost:27017".into());
+    #[clap(subcommand)]
+    command: Commands,
 
-    // Parse your connection string into an options struct
-    let mut client_options =
-        ClientOptions::parse(format!("mongodb://{}:{}@{}/wd_diff", username, password, mongo_url))
-            .await?;
+    /// TOML config file with the connection settings (see config.example.toml)
+    #[clap(long, global = true)]
+    config: Option<String>,
 
-    // Manually set an option
-    client_options.app_name = Some("diff_indexer".to_string());
+    /// MongoDB connection string. Overrides the MONGO_URI and MONGO_URL environment variables and the config file
+    #[clap(long, global = true)]
+    mongo_uri: Option<String>,
 
-    // Get a handle to the cluster
-    return Client::with_options(client_options);
+    /// Database to index into. Overrides the MONGO_DB environment variable and the config file
+    #[clap(long, global = true)]
+    database: Option<String>
 }
 
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Indexes the diff files
+    Index(Box<IndexArgs>),
+    /// Converts the documents indexed by previous versions to the current schema
+    Migrate,
+    /// Exports the entities and revisions indexed in MongoDB to JSON Lines files that can be indexed again
+    Export(ExportArgs)
+}
 
 #[tokio::main]
-async fn main() -> mongodb::error::Result<()> {
+async fn main() -> Result<(), BackendError> {
     let args = Args::parse();
 
-    let client: Client = create_client().await.expect("Error creating MongoDB client");
-    let db_name: String = std::env::var("MONGO_DB").unwrap_or_else(|_| "wd_diff".into());
- 
-    // Ping the server to see if you can connect to the cluster
-    client
-        .database(&db_name)
-        .run_command(doc! {"ping": 1}, None)
-        .await?;
-    
-    // Get a handle to a collection in the database.
-    let db = client.database(&db_name);
-    let entities_collection = db.collection::<MongoEntity>("wd_entities");
-    let revisions_collection = db.collection::<MongoRevision>("wd_revisions");
-
-    let entities_classes = get_entities_classes_dict(args.entities_classes_file);
-
-    // get files in input dir
-    let file_paths = read_dir(args.input_dir).unwrap();
-    let entries = file_paths
-        .filter(|e| {
-            return match e.as_ref().unwrap().path().extension() {
-                Some(ext) => ext == "json",
-                None => false
-            };
-        })   
-        .collect::<Result<Vec<DirEntry>, _>>().expect("Error getting files from input folder");
-
-    // set up progress bar
-    let style = ProgressStyle::default_bar()
-        .template("[{elapsed_precise}] {bar:40.cyan/blue} {msg} {pos:>7}/{len:7} ")
-        .progress_chars("##-");
-    let pb = ProgressBar::new((entries.len() / args.bulk_size) as u64)
-        .with_message("Number of indexes:");
-    pb.set_style(style.clone());
-    
-
-    let mut entities = Vec::<WikidataItem>::with_capacity(args.bulk_size);
-    let mut i = 0;
-    let mut num_instances = 0;
-    
-    for entry in entries {
-        let path = entry.path();
-	    println!("{:?}", path);
-
-        // read file contents into entities vec
-        let file = File::open(&path).expect(&format!("Could not open file: {:?}", &path));
-        let reader = BufReader::new(file);
-
-        entities.append(& mut serde_json::from_reader(reader).unwrap());
-        i += 1;
-
-        if i > args.bulk_size {
-            i = 0;
-            insert_many(&entities_collection, &revisions_collection, &entities, &entities_classes).await;
-
-            num_instances += entities.len();
-            entities.clear();
-            pb.inc(1);
-        }
-    }
-
-    if entities.len() > 0 {
-        num_instances += entities.len();
-        insert_many(&entities_collection, &revisions_collection, &entities, &entities_classes).await;
-    }
-
-    println!("Indexed {:?} entities", num_instances);
-    pb.inc(1);
-    Ok(())
-}
-
-async fn insert_many(entities_collection: &Collection::<MongoEntity>,
-                     revisions_collection: &Collection<MongoRevision>,
-                     entities: & Vec::<WikidataItem>,
-                     entities_classes: &HashMap<String, Vec::<String>>) {
-    let mut mongo_entities = Vec::<MongoEntity>::new();
-    let mut mongo_revisions = Vec::<MongoRevision>::new();
-    for entity in entities {
-        let class_ids: Vec::<String> = match entities_classes.get(&entity.entity_id) {
-            Some(class_ids) => class_ids.clone(),
-            None => {
-                println!("No classes for entity {}", entity.entity_id.clone());
-                Vec::<String>::new()
-            }
-        };
-
-        let m_entity = MongoEntity {id: entity.id, entity_id: entity.entity_id.clone(),
-            entity_json: entity.entity_json.clone(), class_ids: class_ids.clone()};
-        mongo_entities.push(m_entity);
-
-        for rev in entity.revisions.clone() {
-            let mut m_ops = Vec::<MongoOp>::new();
-            match rev.entity_diff {
-                Some(diffs) => {
-                    for diff in diffs {
-                        let m_op = MongoOp {
-                            op: diff.op, path: diff.path, value: diff.value
-                        };
-                        m_ops.push(m_op);
-                    }
-                },
-                None => ()
-            }
-
-            let m_rev = MongoRevision {id: rev.id, entity_id: entity.entity_id.clone(),
-                parent_id: rev.parent_id, timestamp: rev.timestamp,
-                username: rev.username, comment: rev.comment, class_ids: class_ids.clone(),
-                entity_diff: m_ops};
-            mongo_revisions.push(m_rev);
-        }
-    }
-    
-    let result = entities_collection.insert_many(mongo_entities, None).await;
-    if result.is_err() {
-        println!("Error inserting entities documents");
-    }
-    
-    let result2 = revisions_collection.insert_many(mongo_revisions, None).await;
-    if result2.is_err() {
-        println!("Error inserting revisions documents");
-    }
-}
+    let mut config = Config::load(args.config.as_deref());
+    config.mongo.override_with(args.mongo_uri, args.database);
 
-fn get_entities_classes_dict(entities_classes_file: String) -> HashMap<String, Vec::<String>> {
-    let mut entities_classes = HashMap::new();
-    let file = File::open(&entities_classes_file).expect(&format!("Could not open file: {:?}", &entities_classes_file));
-    let mut rdr = csv::Reader::from_reader(BufReader::new(file));
-    for result in rdr.deserialize() {
-        let record: CSVRecord = result.expect("Error parsing CSV record");
-
-        if !entities_classes.contains_key(&record.entity_id) {
-            entities_classes.insert(
-                record.entity_id.clone(),
-                Vec::<String>::new()
-            );
-        }
-        
-        entities_classes.get_mut(&record.entity_id).unwrap().push(record.class_id.clone());
+    match args.command {
+        Commands::Index(index_args) => run_index(config, *index_args).await,
+        Commands::Migrate => run_migrate(&config).await,
+        Commands::Export(export_args) => run_export(&config, export_args).await
     }
-
-    return entities_classes;
-}
-
-fn get_idx_largest_entity(vec: & Vec::<Value>) -> usize {
-    let mut max_size = 0;
-    let mut max_v: Option<&Value> = None;
-    let mut idx = 0;
-    let mut i = 0;
-    for v in vec {
-        if v.to_string().chars().count() * 8 > max_size {
-            max_size = v.to_string().chars().count() * 8;
-            max_v = Some(v);
-            idx = i;
-        }
-
-        i += 1;
-    }
-
-    println!("SIZE: {}", max_size);
-    match max_v {
-        Some(v) => println!("El causante: {:?}", v.get("entity_id")),
-        None => println!("NO")
-    }
-
-    return idx;
 }
@@ -1,60 +1,72 @@
+use mongodb::bson::DateTime;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use wd_history_core::model::PatchOp;
+use wd_history_core::op_path::OpPath;
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
-pub struct WikidataRevision {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedRevision {
+    /// Revision id, used as natural key so the revision is only indexed once
+    #[serde(rename = "_id")]
+    pub doc_id: u64,
     pub id: u64,
+    pub class_ids: Vec::<String>,
+    pub inherited_class_ids: Vec::<String>,
+    pub entity_id: String,
     pub parent_id: u64,
-    pub timestamp: String,
+    pub timestamp: DateTime,
     pub username: String,
     pub comment: String,
-    pub entity_diff: Option<Vec::<WikidataOp>>
-}
-
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
-pub struct WikidataOp {
-    pub op: String,
-    pub path: String,
-    pub value: Option<Value>
+    pub entity_diff: Vec::<IndexedOp>,
+    /// GridFS id of the entity diff, when it was too large to be stored in the document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entity_diff_file: Option<String>,
+    /// Number of documents of the wd_revision_chunks collection the entity diff was split into
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entity_diff_chunks: Option<u32>,
+    /// Date of the dump the revision was indexed from, e.g. 20211101
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dump_version: Option<String>
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
-pub struct WikidataItem {
-    pub id: u64,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MongoRevisionChunk {
+    /// Revision id followed by the chunk index, e.g. 123456_0
+    #[serde(rename = "_id")]
+    pub doc_id: String,
+    pub revision_id: u64,
     pub entity_id: String,
-    pub entity_json: Value,
-    pub revisions: Vec::<WikidataRevision>
+    pub chunk_index: u32,
+    pub entity_diff: Vec::<IndexedOp>
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
-pub struct MongoRevision {
+pub struct IndexedEntity {
+    /// Entity id, used as natural key so the entity is only indexed once
+    #[serde(rename = "_id")]
+    pub doc_id: String,
     pub id: u64,
     pub class_ids: Vec::<String>,
+    pub inherited_class_ids: Vec::<String>,
     pub entity_id: String,
-    pub parent_id: u64,
-    pub timestamp: String,
-    pub username: String,
-    pub comment: String,
-    pub entity_diff: Vec::<MongoOp>
-}
-
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
-pub struct MongoEntity {
-    pub id: u64,
-    pub class_ids: Vec::<String>,
-    pub entity_id: String,
-    pub entity_json: Value
+    pub entity_json: Value,
+    /// GridFS id of the entity JSON, when it was too large to be stored in the document
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entity_json_file: Option<String>,
+    /// Date of the dump the entity JSON was last updated from, e.g. 20211101
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dump_version: Option<String>
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
-pub struct MongoOp {
-    pub op: String,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedOp {
+    pub op: PatchOp,
     pub path: String,
-    pub value: Option<Value>
+    /// Source of move and copy operations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    pub value: Option<Value>,
+    /// Section, property, statement, language... edited by the op, stored next to the path so they can be indexed
+    #[serde(flatten)]
+    pub parsed_path: OpPath
 }
-
-#[derive(Debug, Deserialize)]
-pub struct CSVRecord {
-    pub entity_id: String,
-    pub class_id: String
-}
\ No newline at end of file
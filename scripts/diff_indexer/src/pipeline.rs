@@ -0,0 +1,201 @@
+use crate::backends::{BackendError, FailedDocument, StorageBackend};
+use crate::batch::{Batch, BatchReader};
+use crate::classes::ClassMappings;
+use crate::model::{IndexedEntity, IndexedOp, IndexedRevision};
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use mongodb::bson::DateTime;
+use serde_json::Value;
+use tokio::sync::mpsc::{channel, Receiver, UnboundedSender};
+use tokio::sync::{Mutex, Semaphore};
+use tokio::task::JoinHandle;
+use wd_history_core::model::{WikidataItem, WikidataRevision};
+use wd_history_core::op_path::OpPath;
+use wd_history_core::shard::ShardError;
+
+/// Everything needed to write a batch, shared by the writer tasks
+pub struct IndexContext {
+    pub backend: Box<dyn StorageBackend>,
+    /// Only index the revisions newer than the latest revision indexed for each entity
+    pub incremental: bool,
+    pub classes: ClassMappings
+}
+
+/// Outcome of writing a batch. The entities are dropped once written, only the counts are kept.
+pub struct BatchResult {
+    pub files: Vec<String>,
+    pub completed_files: Vec<(String, usize)>,
+    pub num_entities: usize,
+    pub num_documents: usize,
+    pub num_file_bytes: usize,
+    pub failed: Vec<FailedDocument>
+}
+
+impl IndexContext {
+    /// Upserts the entities and their revisions, returning the documents that could not be written
+    pub async fn write_entities(&self, entities: &[WikidataItem], dump_versions: &[Option<String>]) -> Vec<FailedDocument> {
+        let latest_revision_ids = match self.get_latest_revision_ids(entities).await {
+            Ok(latest_revision_ids) => latest_revision_ids,
+            Err(e) => return entities.iter().map(|entity| FailedDocument {
+                collection: "entities".to_string(),
+                id: Value::from(entity.entity_id.clone()),
+                code: None,
+                error: format!("Error getting the latest indexed revision: {}", e),
+                document: None
+            }).collect()
+        };
+
+        let mut indexed_entities = Vec::<IndexedEntity>::new();
+        let mut indexed_revisions = Vec::<IndexedRevision>::new();
+        let mut failed = Vec::<FailedDocument>::new();
+        for (entity, dump_version) in entities.iter().zip(dump_versions) {
+            // the revisions up to the latest indexed one come from a previous dump, and keep its version
+            let latest_revision_id = latest_revision_ids.get(&entity.entity_id).copied();
+            let revisions = entity.revisions.iter()
+                .filter(|rev| latest_revision_id.is_none_or(|latest_id| rev.id > latest_id))
+                .cloned()
+                .collect::<Vec<WikidataRevision>>();
+            if latest_revision_id.is_some() && revisions.is_empty() {
+                continue;
+            }
+
+            let class_ids: Vec::<String> = match self.classes.get_class_ids(&entity.entity_id) {
+                Some(class_ids) => class_ids,
+                None => {
+                    println!("No classes for entity {}", entity.entity_id.clone());
+                    Vec::<String>::new()
+                }
+            };
+
+            let inherited_class_ids = self.classes.get_inherited_class_ids(&entity.entity_id);
+
+            let m_entity = IndexedEntity {doc_id: entity.entity_id.clone(), id: entity.id, entity_id: entity.entity_id.clone(),
+                entity_json: entity.entity_json.clone(), class_ids: class_ids.clone(),
+                inherited_class_ids: inherited_class_ids.clone(), entity_json_file: None,
+                dump_version: dump_version.clone()};
+            indexed_entities.push(m_entity);
+
+            for rev in revisions {
+                let timestamp = match DateTime::parse_rfc3339_str(&rev.timestamp) {
+                    Ok(timestamp) => timestamp,
+                    Err(e) => {
+                        failed.push(FailedDocument {
+                            collection: "revisions".to_string(),
+                            id: Value::from(rev.id),
+                            code: None,
+                            error: format!("Invalid timestamp {:?}: {}", rev.timestamp, e),
+                            document: serde_json::to_value(&rev).ok()
+                        });
+                        continue;
+                    }
+                };
+
+                let mut m_ops = Vec::<IndexedOp>::new();
+                if let Some(diffs) = rev.entity_diff {
+                    for diff in diffs {
+                        let parsed_path = OpPath::parse(&diff.path, diff.value.as_ref());
+                        let m_op = IndexedOp {
+                            op: diff.op, path: diff.path, from: diff.from, value: diff.value, parsed_path
+                        };
+                        m_ops.push(m_op);
+                    }
+                }
+
+                let m_rev = IndexedRevision {doc_id: rev.id, id: rev.id, entity_id: entity.entity_id.clone(),
+                    parent_id: rev.parent_id, timestamp,
+                    username: rev.username, comment: rev.comment, class_ids: class_ids.clone(),
+                    inherited_class_ids: inherited_class_ids.clone(), entity_diff: m_ops,
+                    entity_diff_file: None, entity_diff_chunks: None, dump_version: dump_version.clone()};
+                indexed_revisions.push(m_rev);
+            }
+        }
+
+        failed.append(&mut self.backend.write(indexed_entities, indexed_revisions).await);
+        failed
+    }
+
+    async fn get_latest_revision_ids(&self, entities: &[WikidataItem]) -> Result<HashMap<String, u64>, BackendError> {
+        if !self.incremental {
+            return Ok(HashMap::new());
+        }
+        let entity_ids = entities.iter().map(|entity| entity.entity_id.clone()).collect::<Vec<String>>();
+        self.backend.get_latest_revision_ids(&entity_ids).await
+    }
+}
+
+/// Starts `num_tasks` blocking tasks that read and parse the input files into batches, sending them to
+/// a queue of `queue_size` batches. Files are assigned to the tasks in turns, and each file is read by a
+/// single task. A task stops at the first file it can't read, returning the error.
+pub fn spawn_parsers(files: Vec<PathBuf>, num_tasks: usize, queue_size: usize, bulk_size: usize,
+                     bulk_bytes: Option<usize>,
+                     dump_version: Option<String>) -> (Receiver<Batch>, Vec<JoinHandle<Result<(), ShardError>>>) {
+    let (sender, receiver) = channel::<Batch>(queue_size);
+    let mut task_files = vec![Vec::<PathBuf>::new(); num_tasks];
+    for (i, file) in files.into_iter().enumerate() {
+        task_files[i % num_tasks].push(file);
+    }
+
+    let handles = task_files.into_iter()
+        .map(|files| {
+            let sender = sender.clone();
+            let dump_version = dump_version.clone();
+            tokio::task::spawn_blocking(move || {
+                for batch in BatchReader::new(files, bulk_size, bulk_bytes, dump_version) {
+                    if sender.blocking_send(batch?).is_err() {
+                        break;
+                    }
+                }
+                Ok(())
+            })
+        })
+        .collect();
+
+    (receiver, handles)
+}
+
+/// Starts `num_tasks` writer tasks taking batches from the queue, each writing up to `max_in_flight`
+/// batches at the same time. The result of each batch is sent to `results`.
+pub fn spawn_writers(batches: Receiver<Batch>, context: Arc<IndexContext>, num_tasks: usize, max_in_flight: usize,
+                     results: UnboundedSender<BatchResult>) -> Vec<JoinHandle<()>> {
+    let batches = Arc::new(Mutex::new(batches));
+    (0..num_tasks)
+        .map(|_| tokio::spawn(run_writer(batches.clone(), context.clone(), max_in_flight, results.clone())))
+        .collect()
+}
+
+async fn run_writer(batches: Arc<Mutex<Receiver<Batch>>>, context: Arc<IndexContext>, max_in_flight: usize,
+                    results: UnboundedSender<BatchResult>) {
+    let in_flight = Arc::new(Semaphore::new(max_in_flight));
+    let mut handles = Vec::new();
+    loop {
+        let permit = in_flight.clone().acquire_owned().await.expect("Error waiting for in-flight batches");
+        let batch = match batches.lock().await.recv().await {
+            Some(batch) => batch,
+            None => break
+        };
+
+        let context = context.clone();
+        let results = results.clone();
+        handles.push(tokio::spawn(async move {
+            let failed = context.write_entities(&batch.entities, &batch.dump_versions).await;
+            // the receiver is only dropped if the main task stops
+            let _ = results.send(BatchResult {
+                files: batch.files,
+                completed_files: batch.completed_files,
+                num_entities: batch.entities.len(),
+                num_documents: batch.num_documents,
+                num_file_bytes: batch.num_file_bytes,
+                failed
+            });
+            drop(permit);
+        }));
+    }
+
+    // wait for the batches still being written
+    for handle in handles {
+        handle.await.expect("Error writing batch");
+    }
+}
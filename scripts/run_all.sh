@@ -1,17 +1,15 @@
 #!/usr/bin/env bash
+# Usage: ./run_all.sh [config file, wdhist.toml by default]
+# Downloads the dumps, computes the diffs and indexes them with the options of the config file
+# (see wdhist/wdhist.example.toml). Works from any directory, including the docker-compose file.
 
-# this line is needed when running from the docker-compose file
-# it can be deleted if the script is run from this folder
-cd scripts/edit_history
+set -e
+cd "$(dirname "$0")"
 
-echo "Downloading dumps..."
-sh download_dumps.sh
-echo "Dumps downloaded"
+CONFIG=${1:-wdhist.toml}
+if [ ! -f "$CONFIG" ]; then
+    echo "Config file $CONFIG not found, using wdhist/wdhist.example.toml"
+    CONFIG=wdhist/wdhist.example.toml
+fi
 
-cd wd_diff_calculator
-cargo build --release
-cargo run --release -- --input-dir ../data/edit_history/raw_dumps --output-dir ../data/edit_history/diffs --bulk-size 2000 --entities-file ../data/edit_history/entities_ids.txt
-
-cd ../diff_indexer
-cargo build --release
-cargo run --release -- --input-dir ../data/edit_history/diffs --entities-classes-file ../notebooks/output/1_data_fetching/entities_classes_ids.csv --bulk-size 2
+cargo run --release -p wdhist -- --config "$CONFIG" run
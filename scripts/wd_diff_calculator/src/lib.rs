@@ -0,0 +1,220 @@
+//! Processes Wikidata meta history dump files to calculate the diff of each entity. The binary is also run
+//! by the diff subcommand of the wdhist command line tool.
+
+mod utils;
+
+use crate::utils::{get_entities_to_fetch, matches_glob, parse_page_range, save_entities_diff};
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{DirEntry, File, read_dir, remove_file};
+use std::io::BufReader;
+use std::path::Path;
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::process::{Command, Stdio};
+
+use clap::Parser;
+use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
+use serde_json::Value;
+use wd_history_core::dump::for_each_history_item;
+use wd_history_core::io::{get_dump_version, get_file_name, DumpPart};
+use wd_history_core::model::WikidataItem;
+use wd_history_core::shard::ShardHeader;
+
+
+/// Processes Wikidata meta history dump files to calculate the diff of each entity and saves results to dir
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+pub struct Args {
+    /// Input directory where the meta history dump files are stored
+    #[clap(short, long)]
+    input_dir: String,
+
+    /// Folder where results will be saved
+    #[clap(short, long)]
+    output_dir: String,
+
+    /// Number of entities to save in each file
+    #[clap(short, long, default_value_t=100)]
+    bulk_size: usize,
+
+
+    /// File containing a list of entities (delimited by newline) which will be processed from the dumps.
+    /// Each entity id may be followed by its page id (e.g. `Q42,138`), and then the dump parts that can't
+    /// contain any of the entities are skipped.
+    #[clap(short, long)]
+    entities_file: Option<String>,
+
+    /// Only process the dump parts with pages in this range of page ids, e.g. 1-100000
+    #[clap(long)]
+    page_range: Option<String>,
+
+    /// Only process the dump files whose names match this pattern, where * matches any text and ? any
+    /// character, e.g. "*history1?.xml*"
+    #[clap(long)]
+    parts_glob: Option<String>
+}
+
+
+fn process_file(file_name: & impl AsRef<Path>, output_dir: & impl AsRef<Path>,
+                entities_to_fetch: Option<&HashSet<String>>, bulk_size: usize, header: &ShardHeader) {
+    let file = File::open(file_name).unwrap();
+
+    // bulk of items saved to each diff file, later indexed by diff_indexer (e.g. into Elasticsearch)
+    let mut item_bulk = Vec::<WikidataItem>::with_capacity(bulk_size);
+    let mut bulk_counter: u8 = 0;
+
+    for_each_history_item(BufReader::new(file), entities_to_fetch, |item| {
+        item_bulk.push(item);
+        if item_bulk.len() > bulk_size {
+            save_entities_diff(&item_bulk, header, file_name, output_dir, &mut bulk_counter);
+            item_bulk.clear();
+        }
+    });
+
+    // saving remaining entities of last bulk after EOF
+    if !item_bulk.is_empty() {
+        save_entities_diff(&item_bulk, header, file_name, output_dir, &mut bulk_counter);
+        item_bulk.clear();
+    }
+}
+
+
+/// Whether a dump file has to be processed, according to the page range, the glob pattern and the page ids of the
+/// entities to fetch. Files whose names have no page range (`p<start>p<end>`) are only filtered by the pattern.
+fn is_selected(file_name: &str, page_range: Option<(u64, u64)>, parts_glob: Option<&str>,
+               page_ids: Option<&[u64]>) -> bool {
+    if parts_glob.is_some_and(|pattern| !matches_glob(pattern, file_name)) {
+        return false;
+    }
+    let part = match DumpPart::parse(file_name) {
+        Some(part) => part,
+        None => return true
+    };
+    if page_range.is_some_and(|(first, last)| part.last_page_id < first || part.first_page_id > last) {
+        return false;
+    }
+    page_ids.is_none_or(|page_ids| {
+        // first page id not lower than the start of the part
+        let i = page_ids.partition_point(|id| *id < part.first_page_id);
+        i < page_ids.len() && page_ids[i] <= part.last_page_id
+    })
+}
+
+
+/// Computes the diffs of the meta history dump files of the input directory
+pub fn run(args: Args) {
+    ThreadPoolBuilder::new().num_threads(5).build_global().unwrap();
+
+    // options saved in the header of every diff file
+    let options = BTreeMap::from([
+        ("bulk_size".to_string(), Value::from(args.bulk_size)),
+        ("entities_file".to_string(), Value::from(args.entities_file.clone())),
+        ("page_range".to_string(), Value::from(args.page_range.clone())),
+        ("parts_glob".to_string(), Value::from(args.parts_glob.clone()))
+    ]);
+
+    let entities_to_fetch = args.entities_file.map(get_entities_to_fetch);
+    let page_range = args.page_range.as_deref().map(parse_page_range);
+    let page_ids = entities_to_fetch.as_ref().and_then(|entities| entities.page_ids.as_deref());
+
+    let file_paths = read_dir(args.input_dir).unwrap();
+
+    // Fail if any dir entry is error
+   let mut entries = file_paths
+    .filter(|e| {
+        match e.as_ref().unwrap().path().extension() {
+            Some(ext) => ext == "7z",
+            None => false
+        }
+    })
+    .collect::<Result<Vec<DirEntry>, _>>().expect("Error getting files from input folder");
+    entries.sort_by_key(|dir| dir.path());
+
+    let num_files = entries.len();
+    entries.retain(|dir| is_selected(&get_file_name(&dir.path()), page_range, args.parts_glob.as_deref(), page_ids));
+    if entries.len() < num_files {
+        println!("Processing {} of the {} dump files of the input folder", entries.len(), num_files);
+    }
+
+    println!("{:?}", entries);
+
+    // set up progress bar
+    let style = ProgressStyle::default_bar()
+        .template("[{elapsed_precise}] {bar:40.cyan/blue} {msg} {pos:>7}/{len:7} ")
+        .progress_chars("##-");
+    let pb = ProgressBar::new(entries.len() as u64)
+        .with_message("Files processed:");
+    pb.set_style(style.clone());
+
+    entries.par_iter().progress_with(pb).for_each(|dir_entry| {
+        let path = dir_entry.path();
+        println!("Extracting file {:?}...", path);
+        let new_filename = path.to_str().unwrap().replace(".7z", ".xml");
+        let fd = File::create(&new_filename).unwrap().into_raw_fd();
+        
+        // from_raw_fd is only considered unsafe if the file is used for mmap
+        let out = unsafe {Stdio::from_raw_fd(fd)};
+
+        let mut child = Command::new("7z")
+            .args(["e", path.to_str().unwrap(), "-so"])
+            .stdout(out)
+            .spawn()
+            .expect("failed to execute process");
+        child.wait().unwrap();
+        
+        println!("File '{:?}' extracted", path);
+
+        println!("Procesing file: {:?}", path);
+        let mut header = ShardHeader::new("wd_diff_calculator", env!("CARGO_PKG_VERSION"));
+        header.dump_date = get_dump_version(&get_file_name(&path));
+        header.source_file = Some(get_file_name(&path));
+        header.options = options.clone();
+        process_file(&new_filename, &args.output_dir, entities_to_fetch.as_ref().map(|entities| &entities.entity_ids),
+                     args.bulk_size, &header);
+        println!("File {:?} has been processed.", path);
+
+        let error_msg = format!("File {} could not be deleted!", new_filename);
+        remove_file(new_filename).expect(&error_msg);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PART_1: &str = "wikidatawiki-20211101-pages-meta-history1.xml-p1p154.7z";
+    const PART_2: &str = "wikidatawiki-20211101-pages-meta-history2.xml-p155p300.7z";
+
+    #[test]
+    fn select_by_page_range() {
+        assert!(is_selected(PART_1, None, None, None));
+        assert!(is_selected(PART_1, Some((100, 200)), None, None));
+        assert!(is_selected(PART_2, Some((100, 200)), None, None));
+        assert!(is_selected(PART_2, Some((300, 300)), None, None));
+        assert!(!is_selected(PART_1, Some((155, 1000)), None, None));
+        assert!(!is_selected(PART_2, Some((1, 154)), None, None));
+    }
+
+    #[test]
+    fn select_by_glob() {
+        assert!(is_selected(PART_1, None, Some("*history1.xml*"), None));
+        assert!(!is_selected(PART_2, None, Some("*history1.xml*"), None));
+        // the pattern applies to the files without page range too
+        assert!(is_selected("wikidatawiki-20211101-pages-meta-history.7z", None, Some("*history*"), None));
+        assert!(!is_selected("wikidatawiki-20211101-pages-meta-history.7z", None, Some("*history1*"), None));
+        assert!(!is_selected(PART_1, Some((1, 10)), Some("*history2.xml*"), None));
+    }
+
+    #[test]
+    fn select_by_page_ids() {
+        assert!(is_selected(PART_1, None, None, Some(&[1, 1000])));
+        assert!(is_selected(PART_1, None, None, Some(&[154])));
+        assert!(is_selected(PART_2, None, None, Some(&[42, 155])));
+        assert!(!is_selected(PART_2, None, None, Some(&[42, 154, 301])));
+        assert!(!is_selected(PART_1, None, None, Some(&[])));
+        // files without page range can't be skipped
+        assert!(is_selected("wikidatawiki-20211101-pages-meta-history.7z", Some((1, 10)), None, Some(&[])));
+    }
+}
@@ -1,25 +1,90 @@
-use crate::model::{WikidataItem};
-
 use std::fs::File;
 use std::io::{BufReader, BufRead};
 use std::collections::HashSet;
 use std::path::Path;
 
+use wd_history_core::model::WikidataItem;
+use wd_history_core::shard::{write_shard, ShardHeader};
+
+
+/// Entities listed in the entities file
+pub struct EntitiesToFetch {
+    pub entity_ids: HashSet<String>,
+    /// Sorted page ids of the entities, used to skip the dump parts that can't contain them. None if some
+    /// entity has no page id.
+    pub page_ids: Option<Vec<u64>>
+}
 
-pub fn get_entities_to_fetch(file_name: impl AsRef<Path>) -> HashSet<String> {
-    let mut entities_to_fetch = HashSet::new();
+/// Reads the entities file: an entity id per line, optionally followed by its page id, separated by a comma,
+/// a tab or spaces (e.g. `Q42,138`)
+pub fn get_entities_to_fetch(file_name: impl AsRef<Path>) -> EntitiesToFetch {
+    let mut entity_ids = HashSet::new();
+    let mut page_ids = Vec::new();
+    let mut num_without_page_id = 0;
 
     let file = File::open(file_name).expect("entities file not found");
     let reader = BufReader::new(file);
 
-    for line in reader.lines() {
-        entities_to_fetch.insert(String::from(line.unwrap().trim()));
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line.unwrap();
+        let mut columns = line.split([',', '\t', ' ']).map(str::trim).filter(|c| !c.is_empty());
+        let entity_id = match columns.next() {
+            Some(entity_id) => entity_id,
+            None => continue
+        };
+        entity_ids.insert(entity_id.to_string());
+        match columns.next() {
+            Some(page_id) => page_ids.push(page_id.parse().unwrap_or_else(|_| {
+                panic!("Invalid page id {:?} in line {} of the entities file", page_id, line_number + 1)
+            })),
+            None => num_without_page_id += 1
+        }
     }
 
-    return entities_to_fetch;
+    if num_without_page_id > 0 {
+        // without page ids every dump part has to be read, which takes days
+        println!("WARNING: {} of the {} entities of the entities file have no page id, so no dump part can be \
+                  skipped and every part is processed. Add the page id of each entity after its id (e.g. Q42,138) \
+                  to only process the parts that can contain them", num_without_page_id, entity_ids.len());
+    }
+    page_ids.sort_unstable();
+    EntitiesToFetch { entity_ids, page_ids: (num_without_page_id == 0).then_some(page_ids) }
+}
+
+/// Parses a range of page ids, e.g. 1-100000
+pub fn parse_page_range(range: &str) -> (u64, u64) {
+    range.split_once('-')
+        .and_then(|(first, last)| Some((first.trim().parse().ok()?, last.trim().parse().ok()?)))
+        .filter(|(first, last)| first <= last)
+        .unwrap_or_else(|| panic!("Invalid page range: {}, it should be like 1-100000", range))
+}
+
+/// Whether a file name matches a glob pattern, where `*` matches any text and `?` any character
+pub fn matches_glob(pattern: &str, file_name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<char>>();
+    let name = file_name.chars().collect::<Vec<char>>();
+    // position in the pattern and the name after the last `*`, to backtrack when the rest doesn't match
+    let (mut p, mut n) = (0, 0);
+    let mut last_star = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            last_star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = last_star {
+            p = star_p + 1;
+            n = star_n + 1;
+            last_star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
 }
 
-pub fn save_entities_diff(item_bulk: &Vec::<WikidataItem>, file_name: impl AsRef<Path>,
+pub fn save_entities_diff(item_bulk: &[WikidataItem], header: &ShardHeader, file_name: impl AsRef<Path>,
                           output_dir: impl AsRef<Path>,current_counter: &mut u8) {
     let final_filename = format!("{}_{}.json", file_name.as_ref().file_stem().unwrap().to_str().unwrap(),
                                  current_counter);
@@ -32,7 +97,34 @@ pub fn save_entities_diff(item_bulk: &Vec::<WikidataItem>, file_name: impl AsRef
         Ok(file) => file,
     };
 
-    serde_json::to_writer(file, item_bulk).expect("There was an error writing the entities diff to a file");
+    write_shard(file, header, item_bulk).expect("There was an error writing the entities diff to a file");
 
     *current_counter += 1;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_glob() {
+        assert!(matches_glob("*", ""));
+        assert!(matches_glob("*history1?.xml*", "wikidatawiki-20211101-pages-meta-history12.xml-p1p154.7z"));
+        assert!(!matches_glob("*history1?.xml*", "wikidatawiki-20211101-pages-meta-history1.xml-p1p154.7z"));
+        assert!(matches_glob("a?c", "abc"));
+        assert!(!matches_glob("a?c", "ac"));
+        assert!(!matches_glob("abc", "abcd"));
+        assert!(matches_glob("abc**", "abc"));
+    }
+
+    #[test]
+    fn match_glob_backtracking() {
+        // the first candidates for the text after each `*` are not the ones that match
+        assert!(matches_glob("*ab", "aab"));
+        assert!(matches_glob("*a*b", "xaxxab"));
+        assert!(matches_glob("*p1p*.7z", "history1.xml-p1p1p154.7z"));
+        assert!(matches_glob("a*b?d", "abxbcbxd"));
+        assert!(!matches_glob("*ab", "aba"));
+        assert!(!matches_glob("a*b*c", "abbb"));
+    }
+}
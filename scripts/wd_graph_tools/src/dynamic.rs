@@ -0,0 +1,243 @@
+use crate::rdf::{datavalue_to_term, Term, RDF, WD, WDNO, WDT, XSD};
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use clap::ArgEnum;
+use serde_json::{Map, Value};
+use wd_history_core::model::WikidataItem;
+use wd_history_core::replay::apply_revision;
+
+/// Namespace of the revision serialization defined in the paper
+pub const UO: &str = "https://purl.org/uniovi/wd-edit-history#";
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum DynamicFormat {
+    /// An operation node linked to its revision, with the subject, property and values that were added or removed
+    NTriples,
+    /// RDF-star annotations on the affected triples, linking them to the revision where they were added or removed
+    RdfStar
+}
+
+/// Properties whose statements were modified by a revision, or None if every statement may have changed
+fn get_edited_properties(item: &WikidataItem, revision_idx: usize) -> Option<BTreeSet<String>> {
+    let mut properties = BTreeSet::new();
+    let diff = match &item.revisions[revision_idx].entity_diff {
+        Some(diff) => diff,
+        None => return Some(properties)
+    };
+
+    for op in diff.iter() {
+        let mut tokens = op.path.split('/').skip(1);
+        match (tokens.next(), tokens.next()) {
+            (Some("claims"), Some(property)) => { properties.insert(property.to_string()); },
+            (Some("claims"), None) | (None, _) => return None,
+            _ => ()
+        }
+    }
+
+    Some(properties)
+}
+
+/// Returns the simple values of the statements of the given properties (all properties if None).
+/// Statements with an unknown value are skipped, since they can't be compared between revisions.
+fn get_simple_values(entity_json: &Value, properties: &Option<BTreeSet<String>>) -> BTreeMap<String, Vec<Term>> {
+    let mut values = BTreeMap::new();
+    let claims = match entity_json.get("claims").and_then(Value::as_object) {
+        Some(claims) => claims,
+        None => return values
+    };
+
+    for (property, statements) in claims {
+        if properties.as_ref().is_some_and(|p| !p.contains(property)) {
+            continue;
+        }
+
+        let property_values: &mut Vec<Term> = values.entry(property.clone()).or_default();
+        for statement in statements.as_array().into_iter().flatten() {
+            let mainsnak = match statement.get("mainsnak") {
+                Some(snak) => snak,
+                None => continue
+            };
+
+            let value = match mainsnak.get("snaktype").and_then(Value::as_str) {
+                Some("value") => datavalue_to_term(mainsnak),
+                Some("novalue") => Some(Term::iri(WDNO, property)),
+                _ => None
+            };
+            if let Some(value) = value {
+                property_values.push(value);
+            }
+        }
+    }
+
+    values
+}
+
+/// Converts the revisions of an entity made between `since` (inclusive) and `until` (exclusive) to RDF.
+/// The whole history is replayed, since each diff is computed against the previous revision.
+pub fn item_to_triples(item: &WikidataItem, format: DynamicFormat, since: Option<&str>,
+                       until: Option<&str>) -> Vec<(Term, Term, Term)> {
+    let mut triples = Vec::new();
+    let mut entity_json = Value::Object(Map::new());
+    let subject = Term::iri(WD, &item.entity_id);
+
+    for (idx, revision) in item.revisions.iter().enumerate() {
+        if until.is_some_and(|until| revision.timestamp.as_str() >= until) {
+            break;
+        }
+
+        let properties = get_edited_properties(item, idx);
+        let prev_values = get_simple_values(&entity_json, &properties);
+        apply_revision(&mut entity_json, &item.entity_id, revision);
+        if since.is_some_and(|since| revision.timestamp.as_str() < since) {
+            continue;
+        }
+        let new_values = get_simple_values(&entity_json, &properties);
+
+        let mut ops = Vec::new();
+        for (property, values) in new_values.iter() {
+            let old_values = prev_values.get(property);
+            for value in values {
+                if !old_values.is_some_and(|old| old.contains(value)) {
+                    ops.push(("add", property, value));
+                }
+            }
+        }
+        for (property, values) in prev_values.iter() {
+            let current_values = new_values.get(property);
+            for value in values {
+                if !current_values.is_some_and(|current| current.contains(value)) {
+                    ops.push(("remove", property, value));
+                }
+            }
+        }
+
+        if ops.is_empty() {
+            continue;
+        }
+
+        let revision_node = Term::iri(UO, &format!("rev{}", revision.id));
+        triples.push((revision_node.clone(), Term::iri(RDF, "type"), Term::iri(UO, "revision")));
+        triples.push((revision_node.clone(), Term::iri(UO, "revEntity"), subject.clone()));
+        triples.push((revision_node.clone(), Term::iri(UO, "timestamp"),
+                      Term::typed_literal(&revision.timestamp, &format!("{}dateTime", XSD))));
+        if !revision.username.is_empty() {
+            triples.push((revision_node.clone(), Term::iri(UO, "user"), Term::literal(&revision.username)));
+        }
+
+        for (op_idx, (op_type, property, value)) in ops.into_iter().enumerate() {
+            match format {
+                DynamicFormat::NTriples => {
+                    let op_node = Term::iri(UO, &format!("op{}_{}", revision.id, op_idx));
+                    let value_predicate = if op_type == "add" { "newObject" } else { "prevObject" };
+                    triples.push((op_node.clone(), Term::iri(RDF, "type"), Term::iri(UO, "operation")));
+                    triples.push((op_node.clone(), Term::iri(UO, "fromRevision"), revision_node.clone()));
+                    triples.push((op_node.clone(), Term::iri(UO, "opType"), Term::iri(UO, op_type)));
+                    triples.push((op_node.clone(), Term::iri(UO, "revSubject"), subject.clone()));
+                    triples.push((op_node.clone(), Term::iri(UO, "revProp"), Term::iri(WDT, property)));
+                    triples.push((op_node, Term::iri(UO, value_predicate), value.clone()));
+                },
+                DynamicFormat::RdfStar => {
+                    let predicate = if op_type == "add" { "addedIn" } else { "removedIn" };
+                    let quoted = Term::Triple(Box::new((subject.clone(), Term::iri(WDT, property), value.clone())));
+                    triples.push((quoted, Term::iri(UO, predicate), revision_node.clone()));
+                }
+            }
+        }
+    }
+
+    triples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn statement(id: &str, value_id: &str) -> Value {
+        json!({
+            "id": id,
+            "mainsnak": {"snaktype": "value", "datavalue": {"type": "wikibase-entityid", "value": {"id": value_id}}},
+            "rank": "normal"
+        })
+    }
+
+    /// Q42 is created as an instance of Q5, which is replaced by Q215627 in the second revision. The third
+    /// revision only adds a label.
+    fn item() -> WikidataItem {
+        serde_json::from_value(json!({
+            "id": 138,
+            "entity_id": "Q42",
+            "entity_json": {},
+            "revisions": [
+                {"id": 1, "parent_id": 0, "timestamp": "2012-10-29T17:00:00Z", "username": "Alice", "comment": "",
+                 "entity_diff": [{"op": "add", "path": "/claims", "value": {"P31": [statement("Q42$1", "Q5")]}}]},
+                {"id": 2, "parent_id": 1, "timestamp": "2013-01-01T00:00:00Z", "username": "", "comment": "",
+                 "entity_diff": [
+                     {"op": "add", "path": "/claims/P31/1", "value": statement("Q42$2", "Q215627")},
+                     {"op": "remove", "path": "/claims/P31/0"}
+                 ]},
+                {"id": 3, "parent_id": 2, "timestamp": "2014-01-01T00:00:00Z", "username": "Bob", "comment": "",
+                 "entity_diff": [
+                     {"op": "add", "path": "/labels", "value": {"en": {"language": "en", "value": "Douglas Adams"}}}
+                 ]}
+            ]
+        })).unwrap()
+    }
+
+    fn annotation(value_id: &str, predicate: &str, revision_id: u64) -> (Term, Term, Term) {
+        let quoted = (Term::iri(WD, "Q42"), Term::iri(WDT, "P31"), Term::iri(WD, value_id));
+        (Term::Triple(Box::new(quoted)), Term::iri(UO, predicate), Term::iri(UO, &format!("rev{}", revision_id)))
+    }
+
+    #[test]
+    fn edited_properties() {
+        let item = item();
+        // the whole claims section was added
+        assert_eq!(get_edited_properties(&item, 0), None);
+        assert_eq!(get_edited_properties(&item, 1), Some(BTreeSet::from(["P31".to_string()])));
+        assert_eq!(get_edited_properties(&item, 2), Some(BTreeSet::new()));
+    }
+
+    #[test]
+    fn rdf_star_annotations() {
+        let triples = item_to_triples(&item(), DynamicFormat::RdfStar, None, None);
+        let annotations = triples.iter().filter(|t| matches!(t.0, Term::Triple(_))).cloned().collect::<Vec<_>>();
+        assert_eq!(annotations, vec![
+            annotation("Q5", "addedIn", 1),
+            annotation("Q215627", "addedIn", 2),
+            annotation("Q5", "removedIn", 2)
+        ]);
+
+        // the revision that only changed a label has no node
+        let revision_node = Term::iri(UO, "rev1");
+        assert!(triples.contains(&(revision_node.clone(), Term::iri(UO, "user"), Term::literal("Alice"))));
+        assert!(triples.contains(&(revision_node, Term::iri(UO, "timestamp"),
+                                   Term::typed_literal("2012-10-29T17:00:00Z", &format!("{}dateTime", XSD)))));
+        assert!(!triples.iter().any(|t| t.0 == Term::iri(UO, "rev3")));
+        // the second revision has no username
+        assert!(!triples.iter().any(|t| t.0 == Term::iri(UO, "rev2") && t.1 == Term::iri(UO, "user")));
+    }
+
+    #[test]
+    fn revisions_in_time_range() {
+        let triples = item_to_triples(&item(), DynamicFormat::RdfStar, Some("2013-01-01T00:00:00Z"), None);
+        let annotations = triples.iter().filter(|t| matches!(t.0, Term::Triple(_))).cloned().collect::<Vec<_>>();
+        assert_eq!(annotations, vec![annotation("Q215627", "addedIn", 2), annotation("Q5", "removedIn", 2)]);
+
+        let triples = item_to_triples(&item(), DynamicFormat::RdfStar, None, Some("2013-01-01T00:00:00Z"));
+        let annotations = triples.iter().filter(|t| matches!(t.0, Term::Triple(_))).cloned().collect::<Vec<_>>();
+        assert_eq!(annotations, vec![annotation("Q5", "addedIn", 1)]);
+    }
+
+    #[test]
+    fn operation_nodes() {
+        let triples = item_to_triples(&item(), DynamicFormat::NTriples, Some("2013-01-01T00:00:00Z"), None);
+        let op_node = Term::iri(UO, "op2_1");
+        assert!(triples.contains(&(op_node.clone(), Term::iri(UO, "fromRevision"), Term::iri(UO, "rev2"))));
+        assert!(triples.contains(&(op_node.clone(), Term::iri(UO, "opType"), Term::iri(UO, "remove"))));
+        assert!(triples.contains(&(op_node.clone(), Term::iri(UO, "revProp"), Term::iri(WDT, "P31"))));
+        assert!(triples.contains(&(op_node, Term::iri(UO, "prevObject"), Term::iri(WD, "Q5"))));
+    }
+}
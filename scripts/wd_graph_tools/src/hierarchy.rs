@@ -0,0 +1,146 @@
+use crate::model::SuperclassRecord;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// Property used by Wikidata to link a class with its superclasses
+pub const SUBCLASS_OF: &str = "P279";
+
+/// Direct subclass of (P279) links between classes. Class ids are interned to keep
+/// the memory usage low, since the hierarchy of Wikidata has millions of classes.
+#[derive(Debug, Default)]
+pub struct ClassHierarchy {
+    ids: Vec<String>,
+    index: HashMap<String, u32>,
+    superclasses: Vec<Vec<u32>>
+}
+
+impl ClassHierarchy {
+    fn intern(&mut self, class_id: &str) -> u32 {
+        if let Some(idx) = self.index.get(class_id) {
+            return *idx;
+        }
+
+        let idx = self.ids.len() as u32;
+        self.ids.push(class_id.to_string());
+        self.index.insert(class_id.to_string(), idx);
+        self.superclasses.push(Vec::new());
+        idx
+    }
+
+    /// Records that `class_id` is a direct subclass of each of the given superclasses
+    pub fn add_superclasses(&mut self, class_id: &str, superclass_ids: &[String]) {
+        let class_idx = self.intern(class_id);
+        for superclass_id in superclass_ids {
+            let superclass_idx = self.intern(superclass_id);
+            if superclass_idx != class_idx && !self.superclasses[class_idx as usize].contains(&superclass_idx) {
+                self.superclasses[class_idx as usize].push(superclass_idx);
+            }
+        }
+    }
+
+    /// Number of classes present in the hierarchy (including root classes)
+    pub fn num_classes(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Returns every ancestor of a class along with its minimum distance to the class.
+    /// Cycles in the hierarchy are ignored, since each class is only visited once.
+    pub fn get_ancestors(&self, class_id: &str) -> Vec<(&str, u32)> {
+        let start = match self.index.get(class_id) {
+            Some(idx) => *idx,
+            None => return Vec::new()
+        };
+
+        let mut visited = HashSet::from([start]);
+
+        let mut ancestors = Vec::new();
+        let mut queue = VecDeque::from([(start, 0)]);
+        while let Some((idx, distance)) = queue.pop_front() {
+            for superclass_idx in &self.superclasses[idx as usize] {
+                if visited.insert(*superclass_idx) {
+                    ancestors.push((self.ids[*superclass_idx as usize].as_str(), distance + 1));
+                    queue.push_back((*superclass_idx, distance + 1));
+                }
+            }
+        }
+
+        ancestors.sort();
+        ancestors
+    }
+
+    /// Saves the transitive closure of the hierarchy as a `class_id,superclass_id,distance` CSV file
+    pub fn save_closure(&self, output_file: impl AsRef<Path>) -> usize {
+        let mut writer = csv::Writer::from_path(output_file.as_ref())
+            .unwrap_or_else(|e| panic!("Could not create file {:?}: {}", output_file.as_ref(), e));
+
+        let mut class_ids = self.ids.iter().collect::<Vec<&String>>();
+        class_ids.sort();
+
+        let style = ProgressStyle::default_bar()
+            .template("[{elapsed_precise}] {bar:40.cyan/blue} {msg} {pos:>7}/{len:7} ")
+            .progress_chars("##-");
+        let pb = ProgressBar::new(class_ids.len() as u64)
+            .with_message("Classes processed:");
+        pb.set_style(style);
+
+        let mut num_records = 0;
+        for class_id in class_ids {
+            for (superclass_id, distance) in self.get_ancestors(class_id) {
+                writer.serialize(SuperclassRecord {
+                    class_id: class_id.clone(),
+                    superclass_id: superclass_id.to_string(),
+                    distance
+                }).expect("Error writing closure record");
+                num_records += 1;
+            }
+            pb.inc(1);
+        }
+
+        writer.flush().expect("Error writing closure file");
+        pb.finish();
+        num_records
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hierarchy(links: &[(&str, &[&str])]) -> ClassHierarchy {
+        let mut hierarchy = ClassHierarchy::default();
+        for (class_id, superclass_ids) in links {
+            let superclass_ids = superclass_ids.iter().map(|id| id.to_string()).collect::<Vec<String>>();
+            hierarchy.add_superclasses(class_id, &superclass_ids);
+        }
+        hierarchy
+    }
+
+    #[test]
+    fn diamond_ancestors() {
+        // Q4 -> Q2 -> Q1, Q4 -> Q3 -> Q1, Q4 -> Q1
+        let hierarchy = hierarchy(&[("Q4", &["Q2", "Q3"]), ("Q2", &["Q1"]), ("Q3", &["Q1"]), ("Q5", &["Q4", "Q1"])]);
+        assert_eq!(hierarchy.num_classes(), 5);
+        assert_eq!(hierarchy.get_ancestors("Q4"), vec![("Q1", 2), ("Q2", 1), ("Q3", 1)]);
+        // the minimum distance is kept
+        assert_eq!(hierarchy.get_ancestors("Q5"), vec![("Q1", 1), ("Q2", 2), ("Q3", 2), ("Q4", 1)]);
+    }
+
+    #[test]
+    fn cyclic_ancestors() {
+        let hierarchy = hierarchy(&[("Q1", &["Q2"]), ("Q2", &["Q3"]), ("Q3", &["Q1", "Q4"]), ("Q5", &["Q5"])]);
+        assert_eq!(hierarchy.get_ancestors("Q1"), vec![("Q2", 1), ("Q3", 2), ("Q4", 3)]);
+        assert_eq!(hierarchy.get_ancestors("Q3"), vec![("Q1", 1), ("Q2", 2), ("Q4", 1)]);
+        // a class is not its own superclass
+        assert!(hierarchy.get_ancestors("Q5").is_empty());
+    }
+
+    #[test]
+    fn root_ancestors() {
+        let hierarchy = hierarchy(&[("Q2", &["Q1"])]);
+        assert!(hierarchy.get_ancestors("Q1").is_empty());
+        assert!(hierarchy.get_ancestors("Q404").is_empty());
+    }
+}
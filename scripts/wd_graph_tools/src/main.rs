@@ -0,0 +1,307 @@
+mod dynamic;
+mod hierarchy;
+mod model;
+mod rank;
+mod rdf;
+mod split;
+mod utils;
+
+use crate::dynamic::{DynamicFormat, item_to_triples};
+use crate::hierarchy::{ClassHierarchy, SUBCLASS_OF};
+use crate::rank::{INSTANCE_OF, LinkGraph, save_records};
+use crate::rdf::{EntityConverter, RdfFormat, RdfMode, RdfWriter};
+use crate::split::{SplitMode, read_triples, remove_unknown_triples, save_tsv, split_by_class, split_by_time};
+use crate::utils::{create_output_file, for_each_diff_item, get_claim_entity_ids,
+                   get_class_hierarchy_dict, OutputFile};
+
+use std::collections::HashMap;
+use std::fs::create_dir_all;
+use std::path::Path;
+
+use clap::{ArgEnum, Parser, Subcommand};
+use serde_json::Value;
+use wd_history_core::dump::for_each_dump_entity;
+use wd_history_core::replay::{get_entity_at, parse_timestamp};
+
+
+/// Builds graphs and datasets from Wikidata entities and their edit history without a database
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(subcommand)]
+    command: Commands
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Computes the transitive closure of the subclass of (P279) hierarchy
+    ClassHierarchy {
+        /// Wikidata JSON entity dump (.json or .json.gz) or folder with the diff files
+        #[clap(short, long)]
+        input: String,
+
+        /// Format of the input
+        #[clap(short = 'f', long, arg_enum, default_value = "diffs")]
+        input_format: InputFormat,
+
+        /// CSV file where the closure will be saved (class_id,superclass_id,distance)
+        #[clap(short, long)]
+        output_file: String
+    },
+
+    /// Computes the PageRank of each item from the links between items, and the ClassRank of each class
+    ClassRank {
+        /// Folder where the diff files are stored
+        #[clap(short, long)]
+        input_dir: String,
+
+        /// CSV file where the PageRank scores will be saved (entity_id,pagerank)
+        #[clap(short, long)]
+        pagerank_file: String,
+
+        /// CSV file where the ClassRank scores will be saved (class_id,classrank,num_instances)
+        #[clap(short, long)]
+        classrank_file: String,
+
+        /// Property linking instances to their classes
+        #[clap(long, default_value = INSTANCE_OF)]
+        class_property: String,
+
+        /// CSV file with the closure of the class hierarchy. If given, the scores of the instances
+        /// are also added to the superclasses of their classes
+        #[clap(long)]
+        class_hierarchy_file: Option<String>,
+
+        /// Damping factor of PageRank
+        #[clap(long, default_value_t = 0.85)]
+        damping: f64,
+
+        /// Maximum number of PageRank iterations
+        #[clap(long, default_value_t = 100)]
+        max_iterations: usize,
+
+        /// Stop iterating when the L1 norm of the change in scores is below this value
+        #[clap(long, default_value_t = 1e-9)]
+        tolerance: f64
+    },
+
+    /// Exports the entities of the diff files as RDF, at their final state or at a given time
+    ExportRdf {
+        /// Folder where the diff files are stored
+        #[clap(short, long)]
+        input_dir: String,
+
+        /// File where the triples will be saved (compressed with gzip if it ends with .gz)
+        #[clap(short, long)]
+        output_file: String,
+
+        /// Serialization format
+        #[clap(short, long, arg_enum, default_value = "n-triples")]
+        format: RdfFormat,
+
+        /// Truthy statements only (wdt:) or the full Wikibase ontology (p:/ps:/pq:/prov:)
+        #[clap(short, long, arg_enum, default_value = "truthy")]
+        mode: RdfMode,
+
+        /// Export the entities as they were at this time (e.g. 2020-01-01T00:00:00Z) instead of their final state
+        #[clap(short, long, parse(try_from_str = parse_timestamp))]
+        timestamp: Option<String>
+    },
+
+    /// Exports the revisions of the diff files as RDF, with a node for each revision linked to the
+    /// statement values it added or removed
+    ExportDynamicRdf {
+        /// Folder where the diff files are stored
+        #[clap(short, long)]
+        input_dir: String,
+
+        /// File where the triples will be saved (compressed with gzip if it ends with .gz)
+        #[clap(short, long)]
+        output_file: String,
+
+        /// Serialization of the revisions
+        #[clap(short, long, arg_enum, default_value = "n-triples")]
+        format: DynamicFormat,
+
+        /// Only export revisions made at or after this time (e.g. 2020-01-01T00:00:00Z)
+        #[clap(long, parse(try_from_str = parse_timestamp))]
+        since: Option<String>,
+
+        /// Only export revisions made before this time (e.g. 2021-01-01, at midnight)
+        #[clap(long, parse(try_from_str = parse_timestamp))]
+        until: Option<String>
+    },
+
+    /// Splits the type assertions of a graph into train, validation and test sets (as PyKEEN TSV files)
+    Split {
+        /// How the type assertions are held out
+        #[clap(short, long, arg_enum, default_value = "stratified")]
+        mode: SplitMode,
+
+        /// N-Triples file exported with export-rdf (random and stratified modes)
+        #[clap(short, long)]
+        triples_file: Option<String>,
+
+        /// Folder where the diff files are stored (temporal mode)
+        #[clap(short, long)]
+        input_dir: Option<String>,
+
+        /// Folder where the train.tsv, valid.tsv and test.tsv files will be saved
+        #[clap(short, long)]
+        output_dir: String,
+
+        /// Predicate of the type assertions
+        #[clap(long, default_value = "http://www.wikidata.org/prop/direct/P31")]
+        type_property: String,
+
+        /// Fraction of the type assertions used for validation (random and stratified modes)
+        #[clap(long, default_value_t = 0.1)]
+        valid_ratio: f64,
+
+        /// Fraction of the type assertions used for testing (random and stratified modes)
+        #[clap(long, default_value_t = 0.1)]
+        test_ratio: f64,
+
+        /// Seed of the random number generator (random and stratified modes)
+        #[clap(long, default_value_t = 42)]
+        seed: u64,
+
+        /// Types added after this time are used for testing (temporal mode)
+        #[clap(long, parse(try_from_str = parse_timestamp))]
+        test_timestamp: Option<String>,
+
+        /// Types added after this time and before the test timestamp are used for validation (temporal mode)
+        #[clap(long, parse(try_from_str = parse_timestamp))]
+        valid_timestamp: Option<String>,
+
+        /// Keep validation and test triples with entities or relations not present in the training set
+        #[clap(long)]
+        keep_unknown: bool
+    }
+}
+
+#[derive(ArgEnum, Clone, Debug)]
+enum InputFormat {
+    /// Folder with the diff files computed by wd_diff_calculator
+    Diffs,
+    /// Wikidata JSON entity dump
+    EntityDump
+}
+
+
+fn build_class_hierarchy(input: &str, input_format: &InputFormat) -> ClassHierarchy {
+    let mut hierarchy = ClassHierarchy::default();
+    let mut add_entity = |entity_id: &str, entity_json: &Value| {
+        let superclass_ids = get_claim_entity_ids(entity_json, SUBCLASS_OF);
+        if !superclass_ids.is_empty() {
+            hierarchy.add_superclasses(entity_id, &superclass_ids);
+        }
+    };
+
+    match input_format {
+        InputFormat::Diffs => for_each_diff_item(input, |item| add_entity(&item.entity_id, &item.entity_json)),
+        InputFormat::EntityDump => for_each_dump_entity(input, |entity| {
+            if let Some(entity_id) = entity.get("id").and_then(Value::as_str) {
+                add_entity(entity_id, &entity);
+            }
+        })
+    }
+
+    hierarchy
+}
+
+pub fn main() {
+    let args = Args::parse();
+
+    match args.command {
+        Commands::ClassHierarchy { input, input_format, output_file } => {
+            println!("Reading subclass of relations from {}...", input);
+            let hierarchy = build_class_hierarchy(&input, &input_format);
+            println!("Found {} classes in the hierarchy", hierarchy.num_classes());
+
+            let num_records = hierarchy.save_closure(&output_file);
+            println!("Saved {} superclass relations to {}", num_records, output_file);
+        },
+        Commands::ClassRank { input_dir, pagerank_file, classrank_file, class_property,
+                              class_hierarchy_file, damping, max_iterations, tolerance } => {
+            println!("Building link graph from {}...", input_dir);
+            let mut graph = LinkGraph::default();
+            for_each_diff_item(&input_dir, |item| graph.add_entity(&item.entity_id, &item.entity_json, &class_property));
+            println!("Graph has {} items and {} links", graph.num_nodes(), graph.num_links());
+
+            let scores = graph.pagerank(damping, max_iterations, tolerance);
+            save_records(&graph.get_pagerank_records(&scores), &pagerank_file);
+            println!("PageRank scores saved to {}", pagerank_file);
+
+            let class_hierarchy = match class_hierarchy_file {
+                Some(f) => get_class_hierarchy_dict(f),
+                None => HashMap::new()
+            };
+            let classrank = graph.get_classrank_records(&scores, &class_hierarchy);
+            save_records(&classrank, &classrank_file);
+            println!("ClassRank scores of {} classes saved to {}", classrank.len(), classrank_file);
+        },
+        Commands::ExportRdf { input_dir, output_file, format, mode, timestamp } => {
+            let mut writer = RdfWriter::new(create_output_file(&output_file), format).expect("Error writing RDF file");
+            let mut converter = EntityConverter::new(mode);
+            let mut num_entities = 0;
+
+            for_each_diff_item(&input_dir, |item| {
+                let entity_json = match get_entity_at(&item, timestamp.as_deref()) {
+                    Some(entity_json) => entity_json,
+                    None => return
+                };
+
+                for (s, p, o) in converter.entity_to_triples(&item.entity_id, &entity_json) {
+                    writer.write_triple(&s, &p, &o).expect("Error writing RDF file");
+                }
+                num_entities += 1;
+            });
+
+            let num_triples = writer.num_triples();
+            writer.finish().and_then(OutputFile::finish).expect("Error writing RDF file");
+            println!("Exported {} triples of {} entities to {}", num_triples, num_entities, output_file);
+        },
+        Commands::ExportDynamicRdf { input_dir, output_file, format, since, until } => {
+            let mut writer = RdfWriter::new(create_output_file(&output_file), RdfFormat::NTriples)
+                .expect("Error writing RDF file");
+
+            for_each_diff_item(&input_dir, |item| {
+                for (s, p, o) in item_to_triples(&item, format, since.as_deref(), until.as_deref()) {
+                    writer.write_triple(&s, &p, &o).expect("Error writing RDF file");
+                }
+            });
+
+            let num_triples = writer.num_triples();
+            writer.finish().and_then(OutputFile::finish).expect("Error writing RDF file");
+            println!("Exported {} triples to {}", num_triples, output_file);
+        },
+        Commands::Split { mode, triples_file, input_dir, output_dir, type_property, valid_ratio, test_ratio,
+                          seed, test_timestamp, valid_timestamp, keep_unknown } => {
+            let mut splits = match mode {
+                SplitMode::Random | SplitMode::Stratified => {
+                    let triples_file = triples_file.expect("A triples file is needed to split the types at random");
+                    split_by_class(read_triples(triples_file), &type_property, mode == SplitMode::Stratified,
+                                   valid_ratio, test_ratio, seed)
+                },
+                SplitMode::Temporal => {
+                    let input_dir = input_dir.expect("The diff files are needed to split the types by time");
+                    let test_timestamp = test_timestamp.expect("A test timestamp is needed to split the types by time");
+                    split_by_time(input_dir, &type_property, valid_timestamp.as_deref(), &test_timestamp)
+                }
+            };
+
+            if !keep_unknown {
+                let num_removed = remove_unknown_triples(&mut splits);
+                println!("Removed {} validation and test triples with entities not present in the training set", num_removed);
+            }
+
+            create_dir_all(&output_dir).expect("Could not create output folder");
+            save_tsv(&splits.train, Path::new(&output_dir).join("train.tsv"));
+            save_tsv(&splits.valid, Path::new(&output_dir).join("valid.tsv"));
+            save_tsv(&splits.test, Path::new(&output_dir).join("test.tsv"));
+            println!("Saved {} training, {} validation and {} test triples to {}",
+                     splits.train.len(), splits.valid.len(), splits.test.len(), output_dir);
+        }
+    }
+}
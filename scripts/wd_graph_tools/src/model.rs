@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SuperclassRecord {
+    pub class_id: String,
+    pub superclass_id: String,
+    pub distance: u32
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PageRankRecord {
+    pub entity_id: String,
+    pub pagerank: f64
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClassRankRecord {
+    pub class_id: String,
+    pub classrank: f64,
+    pub num_instances: u64
+}
@@ -0,0 +1,267 @@
+use crate::model::{ClassRankRecord, PageRankRecord};
+use crate::utils::{get_snak_entity_id, get_statements};
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use serde_json::Value;
+
+/// Property used as class pointer by default when computing ClassRank
+pub const INSTANCE_OF: &str = "P31";
+
+/// Item-to-item link graph, where each statement of an item whose main value is another item
+/// is a link between both of them. Entity ids are interned to keep the memory usage low.
+#[derive(Debug, Default)]
+pub struct LinkGraph {
+    ids: Vec<String>,
+    index: HashMap<String, u32>,
+    links: Vec<Vec<u32>>,
+    classes: Vec<Vec<u32>>
+}
+
+impl LinkGraph {
+    fn intern(&mut self, entity_id: &str) -> u32 {
+        if let Some(idx) = self.index.get(entity_id) {
+            return *idx;
+        }
+
+        let idx = self.ids.len() as u32;
+        self.ids.push(entity_id.to_string());
+        self.index.insert(entity_id.to_string(), idx);
+        self.links.push(Vec::new());
+        self.classes.push(Vec::new());
+        idx
+    }
+
+    /// Adds the links and classes of an entity to the graph
+    pub fn add_entity(&mut self, entity_id: &str, entity_json: &Value, class_property: &str) {
+        let source = self.intern(entity_id);
+        let properties = match entity_json.get("claims").and_then(Value::as_object) {
+            Some(claims) => claims.keys().cloned().collect::<Vec<String>>(),
+            None => return
+        };
+
+        let mut targets = HashSet::new();
+        for property in properties {
+            for statement in get_statements(entity_json, &property) {
+                let target_id = match statement.get("mainsnak").and_then(get_snak_entity_id) {
+                    Some(id) if id.starts_with('Q') => id,
+                    _ => continue
+                };
+
+                let target = self.intern(&target_id);
+                if property == class_property && !self.classes[source as usize].contains(&target) {
+                    self.classes[source as usize].push(target);
+                }
+
+                // parallel links between the same items are only counted once
+                if target != source && targets.insert(target) {
+                    self.links[source as usize].push(target);
+                }
+            }
+        }
+    }
+
+    pub fn num_nodes(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn num_links(&self) -> usize {
+        self.links.iter().map(Vec::len).sum()
+    }
+
+    /// Runs PageRank until the L1 norm of the change between iterations is below `tolerance`.
+    /// The scores of nodes without outgoing links are distributed evenly across the graph.
+    pub fn pagerank(&self, damping: f64, max_iterations: usize, tolerance: f64) -> Vec<f64> {
+        let n = self.ids.len();
+        if n == 0 {
+            return Vec::new();
+        }
+
+        let mut scores = vec![1.0 / n as f64; n];
+        let mut next_scores = vec![0.0; n];
+        let (mut num_iterations, mut delta) = (0, f64::INFINITY);
+        for iteration in 1..=max_iterations {
+            let dangling_sum: f64 = (0..n)
+                .filter(|idx| self.links[*idx].is_empty())
+                .map(|idx| scores[idx])
+                .sum();
+            let base = (1.0 - damping) / n as f64 + damping * dangling_sum / n as f64;
+            next_scores.iter_mut().for_each(|s| *s = base);
+
+            for (source, targets) in self.links.iter().enumerate() {
+                if targets.is_empty() {
+                    continue;
+                }
+
+                let share = damping * scores[source] / targets.len() as f64;
+                for target in targets {
+                    next_scores[*target as usize] += share;
+                }
+            }
+
+            delta = scores.iter().zip(next_scores.iter()).map(|(a, b)| (a - b).abs()).sum();
+            num_iterations = iteration;
+            std::mem::swap(&mut scores, &mut next_scores);
+            if delta < tolerance {
+                break;
+            }
+        }
+
+        if delta < tolerance {
+            println!("PageRank converged after {} iterations (delta={:e})", num_iterations, delta);
+        } else {
+            println!("PageRank did not converge after {} iterations (delta={:e})", num_iterations, delta);
+        }
+
+        scores
+    }
+
+    /// Returns the PageRank score of every entity, sorted from highest to lowest
+    pub fn get_pagerank_records(&self, scores: &[f64]) -> Vec<PageRankRecord> {
+        let mut records = self.ids.iter().zip(scores.iter())
+            .map(|(entity_id, score)| PageRankRecord { entity_id: entity_id.clone(), pagerank: *score })
+            .collect::<Vec<PageRankRecord>>();
+        records.sort_by(|a, b| compare_scores(a.pagerank, b.pagerank).then_with(|| a.entity_id.cmp(&b.entity_id)));
+
+        records
+    }
+
+    /// Computes the ClassRank of each class, which is the sum of the PageRank scores of its instances.
+    /// If a class hierarchy is given, the scores of the instances are also added to the superclasses.
+    pub fn get_classrank_records(&self, scores: &[f64],
+                                 class_hierarchy: &HashMap<String, Vec::<String>>) -> Vec<ClassRankRecord> {
+        let mut classrank = HashMap::<&str, (f64, u64)>::new();
+        for (idx, classes) in self.classes.iter().enumerate() {
+            let mut entity_classes = classes.iter()
+                .map(|class| self.ids[*class as usize].as_str())
+                .collect::<HashSet<&str>>();
+            for class in classes {
+                if let Some(superclasses) = class_hierarchy.get(&self.ids[*class as usize]) {
+                    entity_classes.extend(superclasses.iter().map(String::as_str));
+                }
+            }
+
+            for class_id in entity_classes {
+                let entry = classrank.entry(class_id).or_insert((0.0, 0));
+                entry.0 += scores[idx];
+                entry.1 += 1;
+            }
+        }
+
+        let mut records = classrank.into_iter()
+            .map(|(class_id, (score, num_instances))| ClassRankRecord {
+                class_id: class_id.to_string(), classrank: score, num_instances
+            })
+            .collect::<Vec<ClassRankRecord>>();
+        records.sort_by(|a, b| compare_scores(a.classrank, b.classrank).then_with(|| a.class_id.cmp(&b.class_id)));
+
+        records
+    }
+}
+
+/// Orders scores from highest to lowest
+fn compare_scores(a: f64, b: f64) -> Ordering {
+    b.partial_cmp(&a).unwrap_or(Ordering::Equal)
+}
+
+/// Saves a list of records as a CSV file
+pub fn save_records<T: serde::Serialize>(records: &[T], output_file: impl AsRef<Path>) {
+    let mut writer = csv::Writer::from_path(output_file.as_ref())
+        .unwrap_or_else(|e| panic!("Could not create file {:?}: {}", output_file.as_ref(), e));
+    for record in records {
+        writer.serialize(record).expect("Error writing record");
+    }
+    writer.flush().expect("Error writing CSV file");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn statement(target_id: &str) -> Value {
+        json!({
+            "mainsnak": {"datavalue": {"type": "wikibase-entityid", "value": {"id": target_id}}},
+            "rank": "normal"
+        })
+    }
+
+    /// Q1 links to Q2, Q3 and Q4, Q2 to Q3 and Q3 to Q4, which has no links. Q1 and Q2 are instances of Q3,
+    /// which is an instance of Q4, and Q1 is also an instance of Q4.
+    fn graph() -> LinkGraph {
+        let mut graph = LinkGraph::default();
+        graph.add_entity("Q1", &json!({"claims": {
+            "P31": [statement("Q3"), statement("Q4")],
+            "P50": [statement("Q2"), statement("Q2")]
+        }}), INSTANCE_OF);
+        graph.add_entity("Q2", &json!({"claims": {"P31": [statement("Q3")]}}), INSTANCE_OF);
+        graph.add_entity("Q3", &json!({"claims": {"P31": [statement("Q4")]}}), INSTANCE_OF);
+        graph.add_entity("Q4", &json!({"labels": {}}), INSTANCE_OF);
+        graph
+    }
+
+    /// PageRank scores by entity id, since the nodes are numbered in the order they are found
+    fn get_scores(graph: &LinkGraph, scores: &[f64]) -> HashMap<String, f64> {
+        graph.get_pagerank_records(scores).into_iter().map(|r| (r.entity_id, r.pagerank)).collect()
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn pagerank_with_dangling_node() {
+        let graph = graph();
+        assert_eq!(graph.num_nodes(), 4);
+        assert_eq!(graph.num_links(), 5);
+
+        let scores = graph.pagerank(0.85, 100, 1e-10);
+        let expected = [("Q1", 0.12564589844670276), ("Q2", 0.1612455696733197), ("Q3", 0.29830430389566875),
+                        ("Q4", 0.41480422798430894)];
+        let entity_scores = get_scores(&graph, &scores);
+        for (entity_id, expected) in expected {
+            assert_close(entity_scores[entity_id], expected);
+        }
+        // the score of the dangling node is not lost
+        assert_close(scores.iter().sum(), 1.0);
+
+        let records = graph.get_pagerank_records(&scores);
+        let entity_ids = records.iter().map(|r| r.entity_id.as_str()).collect::<Vec<&str>>();
+        assert_eq!(entity_ids, vec!["Q4", "Q3", "Q2", "Q1"]);
+    }
+
+    #[test]
+    fn pagerank_stops_at_max_iterations() {
+        let graph = graph();
+        let scores = graph.pagerank(0.85, 1, 1e-10);
+        assert!((get_scores(&graph, &scores)["Q4"] - 0.41480422798430894).abs() > 1e-3);
+        assert!(LinkGraph::default().pagerank(0.85, 100, 1e-10).is_empty());
+    }
+
+    #[test]
+    fn classrank_over_closure() {
+        let graph = graph();
+        let scores = graph.pagerank(0.85, 100, 1e-10);
+        let s = get_scores(&graph, &scores);
+
+        let records = graph.get_classrank_records(&scores, &HashMap::new());
+        let classrank = records.iter().map(|r| (r.class_id.as_str(), r.classrank, r.num_instances)).collect::<Vec<_>>();
+        assert_eq!(classrank.len(), 2);
+        assert_eq!((classrank[0].0, classrank[0].2), ("Q4", 2));
+        assert_close(classrank[0].1, s["Q1"] + s["Q3"]);
+        assert_eq!((classrank[1].0, classrank[1].2), ("Q3", 2));
+        assert_close(classrank[1].1, s["Q1"] + s["Q2"]);
+
+        // instances of Q3 are instances of Q4 too, and Q1 is only counted once
+        let class_hierarchy = HashMap::from([("Q3".to_string(), vec!["Q4".to_string()])]);
+        let records = graph.get_classrank_records(&scores, &class_hierarchy);
+        let classrank = records.iter().map(|r| (r.class_id.as_str(), r.classrank, r.num_instances)).collect::<Vec<_>>();
+        assert_eq!((classrank[0].0, classrank[0].2), ("Q4", 3));
+        assert_close(classrank[0].1, s["Q1"] + s["Q2"] + s["Q3"]);
+        assert_eq!((classrank[1].0, classrank[1].2), ("Q3", 2));
+        assert_close(classrank[1].1, s["Q1"] + s["Q2"]);
+    }
+}
@@ -0,0 +1,478 @@
+use crate::utils::get_snak_entity_id;
+
+use std::io::{self, Write};
+
+use clap::ArgEnum;
+use serde_json::Value;
+
+pub const WD: &str = "http://www.wikidata.org/entity/";
+pub const WDT: &str = "http://www.wikidata.org/prop/direct/";
+pub const WDNO: &str = "http://www.wikidata.org/prop/novalue/";
+pub const P: &str = "http://www.wikidata.org/prop/";
+pub const PS: &str = "http://www.wikidata.org/prop/statement/";
+pub const PQ: &str = "http://www.wikidata.org/prop/qualifier/";
+pub const PR: &str = "http://www.wikidata.org/prop/reference/";
+pub const WDS: &str = "http://www.wikidata.org/entity/statement/";
+pub const WDREF: &str = "http://www.wikidata.org/reference/";
+pub const WIKIBASE: &str = "http://wikiba.se/ontology#";
+pub const PROV: &str = "http://www.w3.org/ns/prov#";
+pub const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+pub const RDFS: &str = "http://www.w3.org/2000/01/rdf-schema#";
+pub const XSD: &str = "http://www.w3.org/2001/XMLSchema#";
+pub const GEO: &str = "http://www.opengis.net/ont/geosparql#";
+pub const SCHEMA: &str = "http://schema.org/";
+pub const SKOS: &str = "http://www.w3.org/2004/02/skos/core#";
+
+/// Prefixes declared in Turtle files
+const PREFIXES: [(&str, &str); 17] = [
+    ("wd", WD), ("wdt", WDT), ("wdno", WDNO), ("p", P), ("ps", PS), ("pq", PQ), ("pr", PR),
+    ("wds", WDS), ("wdref", WDREF), ("wikibase", WIKIBASE), ("prov", PROV), ("rdf", RDF),
+    ("rdfs", RDFS), ("xsd", XSD), ("geo", GEO), ("schema", SCHEMA), ("skos", SKOS)
+];
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum RdfFormat {
+    NTriples,
+    Turtle
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum RdfMode {
+    /// Only the best ranked values of each statement (wdt:)
+    Truthy,
+    /// Statements nodes with their qualifiers and references (p:/ps:/pq:/prov:/pr:)
+    Full
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Iri(String),
+    Blank(String),
+    Literal { value: String, datatype: Option<String>, lang: Option<String> },
+    /// Quoted triple used in RDF-star annotations
+    Triple(Box<(Term, Term, Term)>)
+}
+
+impl Term {
+    pub fn iri(namespace: &str, local_name: &str) -> Term {
+        Term::Iri(format!("{}{}", namespace, local_name))
+    }
+
+    pub fn literal(value: &str) -> Term {
+        Term::Literal { value: value.to_string(), datatype: None, lang: None }
+    }
+
+    pub fn typed_literal(value: &str, datatype: &str) -> Term {
+        Term::Literal { value: value.to_string(), datatype: Some(datatype.to_string()), lang: None }
+    }
+
+    pub fn lang_literal(value: &str, lang: &str) -> Term {
+        Term::Literal { value: value.to_string(), datatype: None, lang: Some(lang.to_string()) }
+    }
+}
+
+/// Writes triples as N-Triples or Turtle. Turtle output uses the Wikidata prefixes where possible,
+/// and writes a triple per line so the output can be streamed.
+pub struct RdfWriter<W: Write> {
+    out: W,
+    format: RdfFormat,
+    num_triples: u64
+}
+
+impl<W: Write> RdfWriter<W> {
+    pub fn new(mut out: W, format: RdfFormat) -> io::Result<RdfWriter<W>> {
+        if format == RdfFormat::Turtle {
+            for (prefix, namespace) in PREFIXES {
+                writeln!(out, "@prefix {}: <{}> .", prefix, namespace)?;
+            }
+            writeln!(out)?;
+        }
+
+        Ok(RdfWriter { out, format, num_triples: 0 })
+    }
+
+    pub fn write_triple(&mut self, subject: &Term, predicate: &Term, object: &Term) -> io::Result<()> {
+        let line = format!("{} {} {} .", self.format_term(subject), self.format_term(predicate), self.format_term(object));
+        writeln!(self.out, "{}", line)?;
+        self.num_triples += 1;
+        Ok(())
+    }
+
+    pub fn num_triples(&self) -> u64 {
+        self.num_triples
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn format_term(&self, term: &Term) -> String {
+        match term {
+            Term::Iri(iri) => self.format_iri(iri),
+            Term::Blank(label) => format!("_:{}", label),
+            Term::Literal { value, datatype, lang } => {
+                let value = escape_literal(value);
+                match (datatype, lang) {
+                    (_, Some(lang)) => format!("\"{}\"@{}", value, lang),
+                    (Some(datatype), None) => format!("\"{}\"^^{}", value, self.format_iri(datatype)),
+                    (None, None) => format!("\"{}\"", value)
+                }
+            },
+            Term::Triple(triple) => format!("<< {} {} {} >>", self.format_term(&triple.0),
+                                            self.format_term(&triple.1), self.format_term(&triple.2))
+        }
+    }
+
+    fn format_iri(&self, iri: &str) -> String {
+        if self.format == RdfFormat::Turtle {
+            for (prefix, namespace) in PREFIXES.iter().rev() {
+                if let Some(local_name) = iri.strip_prefix(namespace) {
+                    if is_safe_local_name(local_name) {
+                        return format!("{}:{}", prefix, local_name);
+                    }
+                }
+            }
+        }
+
+        format!("<{}>", iri)
+    }
+}
+
+/// Local names written with a prefix are restricted to a conservative subset of the Turtle grammar
+fn is_safe_local_name(local_name: &str) -> bool {
+    let mut chars = local_name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphanumeric() || c == '_' => chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'),
+        _ => false
+    }
+}
+
+fn escape_literal(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            _ => escaped.push(c)
+        }
+    }
+
+    escaped
+}
+
+/// Wikidata times allow unknown months and days (e.g. `+1952-00-00T00:00:00Z`), which are not valid
+/// in xsd:dateTime. As in the Wikidata RDF dumps, they are replaced by the first month or day.
+fn time_to_xsd(time: &str) -> String {
+    let time = time.strip_prefix('+').unwrap_or(time);
+    let (sign, time) = match time.strip_prefix('-') {
+        Some(t) => ("-", t),
+        None => ("", time)
+    };
+
+    let mut parts = time.splitn(3, '-').map(String::from).collect::<Vec<String>>();
+    for part in parts.iter_mut().skip(1) {
+        if part.starts_with("00") {
+            part.replace_range(0..2, "01");
+        }
+    }
+
+    format!("{}{}", sign, parts.join("-"))
+}
+
+/// Converts the data value of a snak into an RDF term
+pub fn datavalue_to_term(snak: &Value) -> Option<Term> {
+    let datavalue = snak.get("datavalue")?;
+    let value = datavalue.get("value")?;
+    match datavalue.get("type")?.as_str()? {
+        "wikibase-entityid" => get_snak_entity_id(snak).map(|id| Term::iri(WD, &id)),
+        "string" => Some(Term::literal(value.as_str()?)),
+        "monolingualtext" => Some(Term::lang_literal(value.get("text")?.as_str()?, value.get("language")?.as_str()?)),
+        "quantity" => {
+            let amount = value.get("amount")?.as_str()?;
+            Some(Term::typed_literal(amount.strip_prefix('+').unwrap_or(amount), &format!("{}decimal", XSD)))
+        },
+        "time" => Some(Term::typed_literal(&time_to_xsd(value.get("time")?.as_str()?), &format!("{}dateTime", XSD))),
+        "globecoordinate" => {
+            let point = format!("Point({} {})", value.get("longitude")?, value.get("latitude")?);
+            Some(Term::typed_literal(&point, &format!("{}wktLiteral", GEO)))
+        },
+        _ => None
+    }
+}
+
+/// Statement ids use a `$` between the entity id and the GUID, which is replaced by `-` in the RDF dumps
+fn statement_node(statement: &Value) -> Option<Term> {
+    let id = statement.get("id")?.as_str()?;
+    Some(Term::iri(WDS, &id.replace('$', "-")))
+}
+
+/// Converts entities into triples, keeping a counter to generate unique blank nodes for unknown values
+pub struct EntityConverter {
+    mode: RdfMode,
+    num_blank_nodes: u64
+}
+
+impl EntityConverter {
+    pub fn new(mode: RdfMode) -> EntityConverter {
+        EntityConverter { mode, num_blank_nodes: 0 }
+    }
+
+    fn new_blank_node(&mut self) -> Term {
+        self.num_blank_nodes += 1;
+        Term::Blank(format!("b{}", self.num_blank_nodes))
+    }
+
+    /// Returns the value of a snak, or None if the snak is a novalue snak or its value is not supported
+    pub fn snak_to_term(&mut self, snak: &Value) -> Option<Term> {
+        match snak.get("snaktype").and_then(Value::as_str) {
+            Some("value") => datavalue_to_term(snak),
+            Some("somevalue") => Some(self.new_blank_node()),
+            _ => None
+        }
+    }
+
+    /// Returns the triples of an entity
+    pub fn entity_to_triples(&mut self, entity_id: &str, entity_json: &Value) -> Vec<(Term, Term, Term)> {
+        let subject = Term::iri(WD, entity_id);
+        let mut triples = Vec::new();
+
+        if let Some(claims) = entity_json.get("claims").and_then(Value::as_object) {
+            let mut properties = claims.keys().collect::<Vec<&String>>();
+            properties.sort();
+            for property in properties {
+                let statements = match claims[property].as_array() {
+                    Some(statements) => statements,
+                    None => continue
+                };
+
+                match self.mode {
+                    RdfMode::Truthy => self.add_truthy_statements(&subject, property, statements, &mut triples),
+                    RdfMode::Full => self.add_full_statements(&subject, property, statements, &mut triples)
+                }
+            }
+        }
+
+        if self.mode == RdfMode::Full {
+            add_terms(&subject, entity_json, &mut triples);
+        }
+
+        triples
+    }
+
+    fn add_truthy_statements(&mut self, subject: &Term, property: &str, statements: &[Value],
+                             triples: &mut Vec<(Term, Term, Term)>) {
+        for statement in get_best_statements(statements) {
+            let mainsnak = match statement.get("mainsnak") {
+                Some(snak) => snak,
+                None => continue
+            };
+
+            if mainsnak.get("snaktype").and_then(Value::as_str) == Some("novalue") {
+                triples.push((subject.clone(), Term::iri(RDF, "type"), Term::iri(WDNO, property)));
+            } else if let Some(object) = self.snak_to_term(mainsnak) {
+                triples.push((subject.clone(), Term::iri(WDT, property), object));
+            }
+        }
+    }
+
+    fn add_full_statements(&mut self, subject: &Term, property: &str, statements: &[Value],
+                           triples: &mut Vec<(Term, Term, Term)>) {
+        let best_statements = get_best_statements(statements);
+        for statement in statements {
+            let node = match statement_node(statement) {
+                Some(node) => node,
+                None => continue
+            };
+
+            triples.push((subject.clone(), Term::iri(P, property), node.clone()));
+            triples.push((node.clone(), Term::iri(RDF, "type"), Term::iri(WIKIBASE, "Statement")));
+            if best_statements.iter().any(|s| std::ptr::eq(*s, statement)) {
+                triples.push((node.clone(), Term::iri(RDF, "type"), Term::iri(WIKIBASE, "BestRank")));
+            }
+
+            let rank = match statement.get("rank").and_then(Value::as_str) {
+                Some("preferred") => "PreferredRank",
+                Some("deprecated") => "DeprecatedRank",
+                _ => "NormalRank"
+            };
+            triples.push((node.clone(), Term::iri(WIKIBASE, "rank"), Term::iri(WIKIBASE, rank)));
+
+            if let Some(mainsnak) = statement.get("mainsnak") {
+                if mainsnak.get("snaktype").and_then(Value::as_str) == Some("novalue") {
+                    triples.push((node.clone(), Term::iri(RDF, "type"), Term::iri(WDNO, property)));
+                } else if let Some(object) = self.snak_to_term(mainsnak) {
+                    triples.push((node.clone(), Term::iri(PS, property), object));
+                }
+            }
+
+            for (qualifier_property, snak) in get_snaks(statement.get("qualifiers")) {
+                if let Some(object) = self.snak_to_term(snak) {
+                    triples.push((node.clone(), Term::iri(PQ, qualifier_property), object));
+                }
+            }
+
+            for reference in statement.get("references").and_then(Value::as_array).into_iter().flatten() {
+                let hash = match reference.get("hash").and_then(Value::as_str) {
+                    Some(hash) => hash,
+                    None => continue
+                };
+
+                let reference_node = Term::iri(WDREF, hash);
+                triples.push((node.clone(), Term::iri(PROV, "wasDerivedFrom"), reference_node.clone()));
+                triples.push((reference_node.clone(), Term::iri(RDF, "type"), Term::iri(WIKIBASE, "Reference")));
+                for (reference_property, snak) in get_snaks(reference.get("snaks")) {
+                    if let Some(object) = self.snak_to_term(snak) {
+                        triples.push((reference_node.clone(), Term::iri(PR, reference_property), object));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns the statements with the best rank: preferred statements if there are any, or normal statements otherwise
+pub fn get_best_statements(statements: &[Value]) -> Vec<&Value> {
+    let rank = |s: &Value| s.get("rank").and_then(Value::as_str).unwrap_or("normal").to_string();
+    let best_rank = if statements.iter().any(|s| rank(s) == "preferred") { "preferred" } else { "normal" };
+
+    statements.iter().filter(|s| rank(s) == best_rank).collect()
+}
+
+/// Returns the snaks of a qualifiers or references object, sorted by property
+fn get_snaks(snaks: Option<&Value>) -> Vec<(&str, &Value)> {
+    let mut result = Vec::new();
+    if let Some(snaks) = snaks.and_then(Value::as_object) {
+        let mut properties = snaks.keys().collect::<Vec<&String>>();
+        properties.sort();
+        for property in properties {
+            for snak in snaks[property].as_array().into_iter().flatten() {
+                result.push((property.as_str(), snak));
+            }
+        }
+    }
+
+    result
+}
+
+/// Adds the labels, descriptions and aliases of an entity
+fn add_terms(subject: &Term, entity_json: &Value, triples: &mut Vec<(Term, Term, Term)>) {
+    let sections = [("labels", Term::iri(RDFS, "label")), ("descriptions", Term::iri(SCHEMA, "description")),
+                    ("aliases", Term::iri(SKOS, "altLabel"))];
+    for (section, predicate) in sections {
+        let terms = match entity_json.get(section).and_then(Value::as_object) {
+            Some(terms) => terms,
+            None => continue
+        };
+
+        let mut languages = terms.keys().collect::<Vec<&String>>();
+        languages.sort();
+        for language in languages {
+            // aliases have a list of terms in each language
+            let values = match &terms[language] {
+                Value::Array(values) => values.iter().collect::<Vec<&Value>>(),
+                value => vec![value]
+            };
+
+            for value in values {
+                if let Some(text) = value.get("value").and_then(Value::as_str) {
+                    triples.push((subject.clone(), predicate.clone(), Term::lang_literal(text, language)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn xsd_times() {
+        assert_eq!(time_to_xsd("+2001-12-31T00:00:00Z"), "2001-12-31T00:00:00Z");
+        // precision of a month or a year
+        assert_eq!(time_to_xsd("+2019-05-00T00:00:00Z"), "2019-05-01T00:00:00Z");
+        assert_eq!(time_to_xsd("+1952-00-00T00:00:00Z"), "1952-01-01T00:00:00Z");
+        assert_eq!(time_to_xsd("-0500-01-01T00:00:00Z"), "-0500-01-01T00:00:00Z");
+        assert_eq!(time_to_xsd("-13798000000-00-00T00:00:00Z"), "-13798000000-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn safe_local_names() {
+        for local_name in ["Q42", "P31", "Q42-F078E5B3-F9A8-480E-B7AC-D97778CBBEF9", "_b1", "label"] {
+            assert!(is_safe_local_name(local_name), "{} should be safe", local_name);
+        }
+        for local_name in ["", "-Q42", "Q42.", "a/b", "Q42$F078", "#x", "Árbol"] {
+            assert!(!is_safe_local_name(local_name), "{} should not be safe", local_name);
+        }
+    }
+
+    #[test]
+    fn turtle_iris() {
+        let mut writer = RdfWriter::new(Vec::new(), RdfFormat::Turtle).unwrap();
+        writer.write_triple(&Term::iri(WD, "Q42"), &Term::iri(WDT, "P31"), &Term::iri(WD, "Q5")).unwrap();
+        writer.write_triple(&Term::iri(WD, "Q42"), &Term::iri(SCHEMA, "about/x"),
+                            &Term::typed_literal("1952-03-11T00:00:00Z", &format!("{}dateTime", XSD))).unwrap();
+        assert_eq!(writer.num_triples(), 2);
+
+        let output = String::from_utf8(writer.finish().unwrap()).unwrap();
+        let lines = output.lines()
+            .filter(|line| !line.starts_with("@prefix") && !line.is_empty())
+            .collect::<Vec<&str>>();
+        assert_eq!(lines, vec![
+            "wd:Q42 wdt:P31 wd:Q5 .",
+            "wd:Q42 <http://schema.org/about/x> \"1952-03-11T00:00:00Z\"^^xsd:dateTime ."
+        ]);
+
+        let mut writer = RdfWriter::new(Vec::new(), RdfFormat::NTriples).unwrap();
+        writer.write_triple(&Term::iri(WD, "Q42"), &Term::iri(RDFS, "label"), &Term::lang_literal("a \"b\"\n", "en"))
+            .unwrap();
+        assert_eq!(String::from_utf8(writer.finish().unwrap()).unwrap(),
+                   "<http://www.wikidata.org/entity/Q42> <http://www.w3.org/2000/01/rdf-schema#label> \
+                    \"a \\\"b\\\"\\n\"@en .\n");
+    }
+
+    #[test]
+    fn best_statements() {
+        let preferred = json!({"id": "Q42$1", "rank": "preferred"});
+        let normal = json!({"id": "Q42$2", "rank": "normal"});
+        let unranked = json!({"id": "Q42$3"});
+        let deprecated = json!({"id": "Q42$4", "rank": "deprecated"});
+
+        let statements = [normal.clone(), preferred.clone(), deprecated.clone(), unranked.clone()];
+        assert_eq!(get_best_statements(&statements), vec![&preferred]);
+        let statements = [normal.clone(), deprecated.clone(), unranked.clone()];
+        assert_eq!(get_best_statements(&statements), vec![&normal, &unranked]);
+        assert!(get_best_statements(&[deprecated]).is_empty());
+    }
+
+    #[test]
+    fn truthy_and_full_statements() {
+        let entity_json = json!({"claims": {"P569": [
+            {"id": "Q42$A", "rank": "normal", "mainsnak": {"snaktype": "value", "datavalue": {
+                "type": "time", "value": {"time": "+1952-03-00T00:00:00Z", "precision": 10}}}},
+            {"id": "Q42$B", "rank": "preferred", "mainsnak": {"snaktype": "value", "datavalue": {
+                "type": "time", "value": {"time": "+1952-03-11T00:00:00Z", "precision": 11}}}}
+        ]}});
+        let date = |time: &str| Term::typed_literal(time, &format!("{}dateTime", XSD));
+
+        let triples = EntityConverter::new(RdfMode::Truthy).entity_to_triples("Q42", &entity_json);
+        assert_eq!(triples, vec![(Term::iri(WD, "Q42"), Term::iri(WDT, "P569"), date("1952-03-11T00:00:00Z"))]);
+
+        let triples = EntityConverter::new(RdfMode::Full).entity_to_triples("Q42", &entity_json);
+        let normal_node = Term::iri(WDS, "Q42-A");
+        let best_rank = (normal_node.clone(), Term::iri(RDF, "type"), Term::iri(WIKIBASE, "BestRank"));
+        assert!(!triples.contains(&best_rank));
+        let rank = |node: &Term, rank: &str| (node.clone(), Term::iri(WIKIBASE, "rank"), Term::iri(WIKIBASE, rank));
+        assert!(triples.contains(&rank(&normal_node, "NormalRank")));
+        assert!(triples.contains(&(normal_node, Term::iri(PS, "P569"), date("1952-03-01T00:00:00Z"))));
+        let preferred_node = Term::iri(WDS, "Q42-B");
+        assert!(triples.contains(&(preferred_node.clone(), Term::iri(RDF, "type"), Term::iri(WIKIBASE, "BestRank"))));
+        assert!(triples.contains(&rank(&preferred_node, "PreferredRank")));
+    }
+}
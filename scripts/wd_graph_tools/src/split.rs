@@ -0,0 +1,333 @@
+use crate::rdf::{EntityConverter, RdfMode, Term, WD};
+use crate::utils::{create_output_file, for_each_diff_item};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use clap::ArgEnum;
+use flate2::read::MultiGzDecoder;
+use rand::SeedableRng;
+use rand::seq::SliceRandom;
+use rand_chacha::ChaCha8Rng;
+use wd_history_core::model::WikidataItem;
+use wd_history_core::replay::get_entity_at;
+
+pub type Triple = (String, String, String);
+
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum SplitMode {
+    /// Type assertions are held out randomly
+    Random,
+    /// Type assertions are held out randomly within each class
+    Stratified,
+    /// Types added after a point in time are held out
+    Temporal
+}
+
+#[derive(Debug, Default)]
+pub struct Splits {
+    pub train: BTreeSet<Triple>,
+    pub valid: BTreeSet<Triple>,
+    pub test: BTreeSet<Triple>
+}
+
+/// Reads the triples between IRIs of an N-Triples file (optionally gzipped).
+/// Triples with literals or blank nodes are skipped, since they can't be used as KG embeddings input.
+/// Panics on the first line that is not an N-Triples statement, e.g. if the file is in Turtle.
+pub fn read_triples(file_name: impl AsRef<Path>) -> BTreeSet<Triple> {
+    let path = file_name.as_ref();
+    let file = File::open(path).unwrap_or_else(|e| panic!("Could not open file {:?}: {}", path, e));
+    let reader: Box<dyn Read> = match path.extension() {
+        Some(ext) if ext == "gz" => Box::new(MultiGzDecoder::new(file)),
+        _ => Box::new(file)
+    };
+
+    let mut triples = BTreeSet::new();
+    for (line_number, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line.unwrap_or_else(|e| panic!("Error reading {:?}: {}", path, e));
+        let statement = line.trim();
+        if statement.is_empty() || statement.starts_with('#') {
+            continue;
+        }
+        if !(statement.starts_with('<') || statement.starts_with("_:")) || !statement.ends_with('.') {
+            panic!("Line {} of {:?} is not an N-Triples statement ({}). Only N-Triples files can be split, \
+                    export the graph with export-rdf --format n-triples", line_number + 1, path, statement);
+        }
+
+        let tokens = statement.split_whitespace().collect::<Vec<&str>>();
+        if tokens.len() != 4 || tokens[3] != "." {
+            continue;
+        }
+
+        let iris = tokens[..3].iter()
+            .filter_map(|t| t.strip_prefix('<').and_then(|t| t.strip_suffix('>')))
+            .collect::<Vec<&str>>();
+        if iris.len() == 3 {
+            triples.insert((iris[0].to_string(), iris[1].to_string(), iris[2].to_string()));
+        }
+    }
+
+    triples
+}
+
+/// Shuffles a list of triples and splits it into train, validation and test lists.
+/// At least one triple is kept for training, so every class is known by the trained models.
+fn split_triples(mut triples: Vec<Triple>, valid_ratio: f64, test_ratio: f64,
+                 rng: &mut ChaCha8Rng, splits: &mut Splits) {
+    triples.shuffle(rng);
+
+    let max_held_out = triples.len().saturating_sub(1);
+    let num_test = ((triples.len() as f64 * test_ratio).round() as usize).min(max_held_out);
+    let num_valid = ((triples.len() as f64 * valid_ratio).round() as usize).min(max_held_out - num_test);
+
+    let mut triples = triples.into_iter();
+    splits.test.extend(triples.by_ref().take(num_test));
+    splits.valid.extend(triples.by_ref().take(num_valid));
+    splits.train.extend(triples);
+}
+
+/// Holds out a fraction of the type assertions of a graph, either at random or within each class.
+/// The rest of the triples are used for training.
+pub fn split_by_class(triples: BTreeSet<Triple>, type_property: &str, stratified: bool,
+                      valid_ratio: f64, test_ratio: f64, seed: u64) -> Splits {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut splits = Splits::default();
+    let mut type_triples = BTreeMap::<String, Vec<Triple>>::new();
+    for triple in triples {
+        if triple.1 != type_property {
+            splits.train.insert(triple);
+        } else if stratified {
+            type_triples.entry(triple.2.clone()).or_default().push(triple);
+        } else {
+            type_triples.entry(String::new()).or_default().push(triple);
+        }
+    }
+
+    // classes are visited in order and their triples are sorted, so the result only depends on the seed
+    for (_, class_triples) in type_triples {
+        split_triples(class_triples, valid_ratio, test_ratio, &mut rng, &mut splits);
+    }
+
+    splits
+}
+
+/// Returns the triples between entities of an entity at the given time, or at its final state
+fn get_entity_triples(entity_id: &str, entity_json: &serde_json::Value, converter: &mut EntityConverter) -> Vec<Triple> {
+    converter.entity_to_triples(entity_id, entity_json).into_iter()
+        .filter_map(|triple| match triple {
+            (Term::Iri(s), Term::Iri(p), Term::Iri(o)) if o.starts_with(WD) => Some((s, p, o)),
+            _ => None
+        })
+        .collect()
+}
+
+/// Splits the graph by time: the graph at `valid_timestamp` (or `test_timestamp`) is used for training,
+/// the types added up to `test_timestamp` for validation, and the types added later for testing.
+/// Only the types that are still present in the final state of the entities are held out.
+pub fn split_by_time(input_dir: impl AsRef<Path>, type_property: &str, valid_timestamp: Option<&str>,
+                     test_timestamp: &str) -> Splits {
+    let mut splits = Splits::default();
+    let mut converter = EntityConverter::new(RdfMode::Truthy);
+    for_each_diff_item(input_dir, |item| {
+        split_item_by_time(&item, type_property, valid_timestamp, test_timestamp, &mut converter, &mut splits);
+    });
+
+    splits
+}
+
+/// Adds the triples of an entity to the temporal splits (see `split_by_time`)
+fn split_item_by_time(item: &WikidataItem, type_property: &str, valid_timestamp: Option<&str>, test_timestamp: &str,
+                      converter: &mut EntityConverter, splits: &mut Splits) {
+    let train_timestamp = valid_timestamp.unwrap_or(test_timestamp);
+    let final_triples = get_entity_triples(&item.entity_id, &item.entity_json, converter);
+    let at = |timestamp: &str, converter: &mut EntityConverter| match get_entity_at(item, Some(timestamp)) {
+        Some(entity_json) => get_entity_triples(&item.entity_id, &entity_json, converter).into_iter().collect(),
+        None => BTreeSet::new()
+    };
+
+    let train_triples: BTreeSet<Triple> = at(train_timestamp, converter);
+    let valid_triples: BTreeSet<Triple> = match valid_timestamp {
+        Some(_) => at(test_timestamp, converter),
+        None => train_triples.clone()
+    };
+
+    for triple in final_triples {
+        if triple.1 != type_property || train_triples.contains(&triple) {
+            continue;
+        }
+
+        if valid_triples.contains(&triple) {
+            splits.valid.insert(triple);
+        } else {
+            splits.test.insert(triple);
+        }
+    }
+    splits.train.extend(train_triples);
+}
+
+/// Removes the validation and test triples whose entities or relation are not part of the training triples.
+/// Returns the number of triples removed.
+pub fn remove_unknown_triples(splits: &mut Splits) -> usize {
+    let mut entities = BTreeSet::new();
+    let mut relations = BTreeSet::new();
+    for (s, p, o) in splits.train.iter() {
+        entities.insert(s.as_str());
+        entities.insert(o.as_str());
+        relations.insert(p.as_str());
+    }
+
+    let is_known = |(s, p, o): &Triple| entities.contains(s.as_str()) && entities.contains(o.as_str())
+        && relations.contains(p.as_str());
+    let num_triples = splits.valid.len() + splits.test.len();
+    splits.valid.retain(is_known);
+    splits.test.retain(is_known);
+
+    num_triples - splits.valid.len() - splits.test.len()
+}
+
+/// Saves triples as a tab separated file with a triple per line, as read by PyKEEN's TriplesFactory
+pub fn save_tsv(triples: &BTreeSet<Triple>, output_file: impl AsRef<Path>) {
+    let mut file = create_output_file(output_file);
+    for (s, p, o) in triples {
+        writeln!(file, "{}\t{}\t{}", s, p, o).expect("Error writing triples file");
+    }
+    file.finish().expect("Error writing triples file");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rdf::WDT;
+
+    use serde_json::{json, Value};
+
+    const INSTANCE_OF: &str = "http://www.wikidata.org/prop/direct/P31";
+
+    /// 60 type assertions of 3 classes and some other triples
+    fn triples() -> BTreeSet<Triple> {
+        let mut triples = BTreeSet::new();
+        for i in 0..60 {
+            let entity = format!("{}Q{}", WD, 1000 + i);
+            triples.insert((entity.clone(), INSTANCE_OF.to_string(), format!("{}Q{}", WD, 5 + i % 3)));
+            triples.insert((entity, format!("{}P50", WDT), format!("{}Q42", WD)));
+        }
+        triples
+    }
+
+    fn save_splits(splits: &Splits, output_dir: &Path) -> Vec<Vec<u8>> {
+        std::fs::create_dir_all(output_dir).unwrap();
+        [("train.tsv", &splits.train), ("valid.tsv", &splits.valid), ("test.tsv", &splits.test)].iter()
+            .map(|(file_name, triples)| {
+                save_tsv(triples, output_dir.join(file_name));
+                std::fs::read(output_dir.join(file_name)).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn seeded_splits() {
+        let work_dir = std::env::temp_dir().join(format!("wd_graph_tools_split_test_{}", std::process::id()));
+        for stratified in [false, true] {
+            let splits = split_by_class(triples(), INSTANCE_OF, stratified, 0.2, 0.2, 7);
+            assert_eq!((splits.train.len(), splits.valid.len(), splits.test.len()), (96, 12, 12));
+            assert!(splits.train.iter().chain(splits.valid.iter()).chain(splits.test.iter())
+                .all(|triple| triples().contains(triple)));
+            assert!(splits.valid.iter().chain(splits.test.iter()).all(|triple| triple.1 == INSTANCE_OF));
+
+            // the same seed gives the same files
+            let files = save_splits(&splits, &work_dir.join("a"));
+            let same_seed = split_by_class(triples(), INSTANCE_OF, stratified, 0.2, 0.2, 7);
+            assert_eq!(save_splits(&same_seed, &work_dir.join("b")), files);
+
+            let other_seed = split_by_class(triples(), INSTANCE_OF, stratified, 0.2, 0.2, 8);
+            assert_ne!(other_seed.test, splits.test);
+        }
+        std::fs::remove_dir_all(&work_dir).unwrap();
+    }
+
+    #[test]
+    fn stratified_split() {
+        let splits = split_by_class(triples(), INSTANCE_OF, true, 0.2, 0.2, 7);
+        for class in ["Q5", "Q6", "Q7"] {
+            let count = |triples: &BTreeSet<Triple>| triples.iter().filter(|t| t.2.ends_with(class)).count();
+            assert_eq!((count(&splits.train), count(&splits.valid), count(&splits.test)), (12, 4, 4));
+        }
+
+        // a class with a single instance keeps it for training
+        let triples = BTreeSet::from([("a".to_string(), INSTANCE_OF.to_string(), "b".to_string())]);
+        let splits = split_by_class(triples, INSTANCE_OF, true, 0.5, 0.5, 7);
+        assert_eq!((splits.train.len(), splits.valid.len(), splits.test.len()), (1, 0, 0));
+    }
+
+    fn type_statement(id: &str, class_id: &str) -> Value {
+        json!({
+            "id": id,
+            "mainsnak": {"snaktype": "value", "datavalue": {"type": "wikibase-entityid", "value": {"id": class_id}}},
+            "rank": "normal"
+        })
+    }
+
+    fn revision(id: u64, timestamp: &str, entity_diff: Value) -> Value {
+        json!({"id": id, "parent_id": id - 1, "timestamp": timestamp, "username": "", "comment": "",
+               "entity_diff": entity_diff})
+    }
+
+    /// Q1 is an instance of Q5 since 2019, Q6 since mid 2020 and Q7 since mid 2021, and was an instance of Q8
+    /// from 2020 to 2021
+    fn item() -> WikidataItem {
+        let entity_json = json!({"claims": {"P31": [
+            type_statement("Q1$5", "Q5"), type_statement("Q1$6", "Q6"), type_statement("Q1$7", "Q7")
+        ]}});
+        serde_json::from_value(json!({
+            "id": 1,
+            "entity_id": "Q1",
+            "entity_json": entity_json,
+            "revisions": [
+                revision(1, "2019-01-01T00:00:00Z", json!([
+                    {"op": "add", "path": "/claims", "value": {"P31": [type_statement("Q1$5", "Q5")]}}
+                ])),
+                revision(2, "2020-02-01T00:00:00Z", json!([
+                    {"op": "add", "path": "/claims/P31/1", "value": type_statement("Q1$8", "Q8")}
+                ])),
+                revision(3, "2020-06-01T00:00:00Z", json!([
+                    {"op": "replace", "path": "/claims/P31/1", "value": type_statement("Q1$6", "Q6")}
+                ])),
+                revision(4, "2021-06-01T00:00:00Z", json!([
+                    {"op": "add", "path": "/claims/P31/2", "value": type_statement("Q1$7", "Q7")}
+                ]))
+            ]
+        })).unwrap()
+    }
+
+    fn type_triple(class_id: &str) -> Triple {
+        (format!("{}Q1", WD), INSTANCE_OF.to_string(), format!("{}{}", WD, class_id))
+    }
+
+    #[test]
+    fn temporal_split() {
+        let mut converter = EntityConverter::new(RdfMode::Truthy);
+        let mut splits = Splits::default();
+        split_item_by_time(&item(), INSTANCE_OF, Some("2020-01-01T00:00:00Z"), "2021-01-01T00:00:00Z",
+                           &mut converter, &mut splits);
+        assert_eq!(splits.train, BTreeSet::from([type_triple("Q5")]));
+        // Q8 is no longer a type of Q1
+        assert_eq!(splits.valid, BTreeSet::from([type_triple("Q6")]));
+        assert_eq!(splits.test, BTreeSet::from([type_triple("Q7")]));
+
+        // without validation timestamp, the graph at the test timestamp is used for training
+        let mut splits = Splits::default();
+        split_item_by_time(&item(), INSTANCE_OF, None, "2021-01-01T00:00:00Z", &mut converter, &mut splits);
+        assert_eq!(splits.train, BTreeSet::from([type_triple("Q5"), type_triple("Q6")]));
+        assert!(splits.valid.is_empty());
+        assert_eq!(splits.test, BTreeSet::from([type_triple("Q7")]));
+
+        // entities created after the training timestamp are only in the held out sets
+        let mut splits = Splits::default();
+        split_item_by_time(&item(), INSTANCE_OF, None, "2018-01-01T00:00:00Z", &mut converter, &mut splits);
+        assert!(splits.train.is_empty());
+        assert_eq!(splits.test.len(), 3);
+    }
+}
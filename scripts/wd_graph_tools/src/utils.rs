@@ -0,0 +1,120 @@
+use crate::model::SuperclassRecord;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use serde_json::Value;
+use wd_history_core::io::get_diff_files;
+use wd_history_core::model::WikidataItem;
+use wd_history_core::shard::read_items;
+
+
+/// Calls `f` with each item stored in the diff files of the given directory
+pub fn for_each_diff_item<F: FnMut(WikidataItem)>(input_dir: impl AsRef<Path>, mut f: F) {
+    for path in get_diff_files(input_dir) {
+        for item in read_items(&path).unwrap_or_else(|e| panic!("{}", e)) {
+            f(item);
+        }
+    }
+}
+
+/// Returns the id of the entity referenced by a snak, if it has an entity as value
+pub fn get_snak_entity_id(snak: &Value) -> Option<String> {
+    let datavalue = snak.get("datavalue")?;
+    if datavalue.get("type")?.as_str()? != "wikibase-entityid" {
+        return None;
+    }
+
+    let value = datavalue.get("value")?;
+    if let Some(id) = value.get("id").and_then(Value::as_str) {
+        return Some(id.to_string());
+    }
+
+    // older revisions only include the numeric id of the entity
+    let numeric_id = value.get("numeric-id")?.as_u64()?;
+    match value.get("entity-type")?.as_str()? {
+        "item" => Some(format!("Q{}", numeric_id)),
+        "property" => Some(format!("P{}", numeric_id)),
+        _ => None
+    }
+}
+
+/// Returns the statements of a property, skipping the deprecated ones
+pub fn get_statements<'a>(entity_json: &'a Value, property: &str) -> Vec<&'a Value> {
+    // empty claims are serialized as an empty array in the dumps
+    match entity_json.get("claims").and_then(|claims| claims.get(property)).and_then(Value::as_array) {
+        Some(statements) => statements.iter()
+            .filter(|s| s.get("rank").and_then(Value::as_str) != Some("deprecated"))
+            .collect(),
+        None => Vec::new()
+    }
+}
+
+/// Returns the ids of the entities used as main value of the statements of a property
+pub fn get_claim_entity_ids(entity_json: &Value, property: &str) -> Vec<String> {
+    get_statements(entity_json, property).into_iter()
+        .filter_map(|s| s.get("mainsnak").and_then(get_snak_entity_id))
+        .collect()
+}
+
+/// Reads the closure of the class hierarchy computed by the class-hierarchy subcommand
+pub fn get_class_hierarchy_dict(class_hierarchy_file: impl AsRef<Path>) -> HashMap<String, Vec::<String>> {
+    let path = class_hierarchy_file.as_ref();
+    let mut rdr = csv::Reader::from_path(path)
+        .unwrap_or_else(|e| panic!("Could not open file {:?}: {}", path, e));
+
+    let mut class_hierarchy = HashMap::<String, Vec::<String>>::new();
+    for result in rdr.deserialize() {
+        let record: SuperclassRecord = result.expect("Error parsing class hierarchy record");
+        class_hierarchy.entry(record.class_id).or_default().push(record.superclass_id);
+    }
+
+    class_hierarchy
+}
+
+/// An output file, compressed with gzip if its name ends with .gz
+pub enum OutputFile {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>)
+}
+
+impl OutputFile {
+    /// Writes the end of the gzip stream and flushes the file. Must be called once everything is written:
+    /// dropping the file instead would lose the errors of the last writes.
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            OutputFile::Plain(mut writer) => writer.flush(),
+            OutputFile::Gzip(encoder) => encoder.finish()?.flush()
+        }
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            OutputFile::Plain(writer) => writer.write(buf),
+            OutputFile::Gzip(encoder) => encoder.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            OutputFile::Plain(writer) => writer.flush(),
+            OutputFile::Gzip(encoder) => encoder.flush()
+        }
+    }
+}
+
+/// Creates an output file, compressing it with gzip if its name ends with .gz
+pub fn create_output_file(file_name: impl AsRef<Path>) -> OutputFile {
+    let path = file_name.as_ref();
+    let file = File::create(path).unwrap_or_else(|e| panic!("Could not create file {:?}: {}", path, e));
+    match path.extension() {
+        Some(ext) if ext == "gz" => OutputFile::Gzip(GzEncoder::new(BufWriter::new(file), Compression::default())),
+        _ => OutputFile::Plain(BufWriter::new(file))
+    }
+}
@@ -0,0 +1,164 @@
+use crate::io::open_input_file;
+use crate::model::{from_patch, WikidataItem, WikidataRevision};
+
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use json_patch::diff;
+use quick_xml::Reader;
+use quick_xml::events::Event;
+use quick_xml::events::BytesStart;
+use serde_json::{Map, Value};
+
+/// Parses a meta history dump (the XML of the pages-meta-history files) and calls `f` with each entity,
+/// with the diff of each revision computed against the previous one. Only the entities in
+/// `entities_to_fetch` are parsed if it is given, and pages whose content is not JSON are skipped.
+pub fn for_each_history_item<R: BufRead, F: FnMut(WikidataItem)>(reader: R, entities_to_fetch: Option<&HashSet<String>>,
+                                                                  mut f: F) {
+    let mut xml_reader = Reader::from_reader(reader);
+    xml_reader.trim_text(true);
+
+    let mut buf = Vec::new();
+
+    // keep tag state to then fetch text correctly
+    let mut current_tag: &[u8] = b"";
+    let mut owned_name;
+
+    // know if we should process the given entity and if the format valid (application/json)
+    let mut valid_entity: bool = false;
+    let mut valid_format: bool = false;
+
+    // know where we are in the tree
+    let mut inside_revision: bool = false;
+    let mut inside_contributor: bool = false;
+
+    // keep state of previous entity json to compute diff
+    let mut previous_json: Option<Value> = None;
+
+    // keep state of current Wikidata item and revision being parsed
+    let mut current_item = WikidataItem::default();
+    let mut current_revision = WikidataRevision::default();
+
+    // The `Reader` does not implement `Iterator` because it outputs borrowed data (`Cow`s)
+    loop {
+        match xml_reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) => {
+                owned_name = BytesStart::owned_name(e.name());
+                current_tag = owned_name.name();
+
+                match current_tag {
+                    b"contributor" => {
+                        inside_contributor = true;
+                    },
+                    b"page" => {
+                        // reset state for next page
+                        current_item = WikidataItem::default();
+                        previous_json = None;
+                        valid_entity = false;
+                    },
+                    b"revision" => {
+                        // reset state for next revision
+                        current_revision = WikidataRevision::default();
+                        valid_format = false;
+                        inside_revision = true;
+                    },
+                    _ => ()
+                }
+            },
+            Ok(Event::Text(e)) => {
+                match current_tag {
+                    b"title" => {
+                        current_item.entity_id = e.unescape_and_decode(&xml_reader).unwrap();
+
+                        match entities_to_fetch {
+                            Some(entities) => valid_entity = entities.contains(&current_item.entity_id),
+                            None => valid_entity = true
+                        }
+                    },
+                    b"comment" => {
+                        current_revision.comment = e.unescape_and_decode(&xml_reader).unwrap();
+                    },
+                    b"format" => {
+                        let content_type = e.unescape_and_decode(&xml_reader).unwrap();
+                        valid_format = content_type == "application/json";
+                    },
+                    b"id" => {
+                        if inside_revision && !inside_contributor {
+                            current_revision.id = e.unescape_and_decode(&xml_reader).unwrap().parse::<u64>().unwrap();
+                        } else if !inside_revision {
+                            current_item.id = e.unescape_and_decode(&xml_reader).unwrap().parse::<u64>().unwrap();
+                        }
+                    },
+                    b"parentid" => {
+                        current_revision.parent_id = e.unescape_and_decode(&xml_reader).unwrap().parse::<u64>().unwrap();
+                    },
+                    b"timestamp" => {
+                        current_revision.timestamp = e.unescape_and_decode(&xml_reader).unwrap();
+                    },
+                    b"text" => {
+                        if !valid_entity || !valid_format {
+                            continue;
+                        }
+
+                        let entity_json: Value = serde_json::from_str(&e.unescape_and_decode(&xml_reader).unwrap()).unwrap();
+
+                        let left = previous_json.unwrap_or_else(|| Value::Object(Map::new()));
+                        current_revision.entity_diff = Some(from_patch(&diff(&left, &entity_json)));
+
+                        previous_json = Some(entity_json);
+                    },
+                    b"username" => {
+                        current_revision.username = e.unescape_and_decode(&xml_reader).unwrap();
+                    },
+                    _ => ()
+                }
+            },
+            Ok(Event::End(ref e)) => {
+                match e.name() {
+                    b"contributor" => {
+                        inside_contributor = false;
+                    },
+                    b"page" => {
+                        if !valid_entity || !valid_format {
+                            continue;
+                        }
+
+                        current_item.entity_json = previous_json.clone().unwrap();
+                        f(std::mem::take(&mut current_item));
+                    },
+                    b"revision" => {
+                        current_item.revisions.push(current_revision.clone());
+                        inside_revision = false;
+                    },
+                    _ => ()
+                }
+            }
+            Ok(Event::Eof) => break, // exits the loop when reaching end of file
+            Err(e) => panic!("Error at position {}: {:?}", xml_reader.buffer_position(), e),
+            _ => ()
+        }
+
+        // if we don't keep a borrow elsewhere, we can clear the buffer to keep memory usage low
+        buf.clear();
+    }
+}
+
+/// Calls `f` with each entity of a Wikidata JSON dump (one entity per line, optionally compressed)
+pub fn for_each_dump_entity<F: FnMut(Value)>(file_name: impl AsRef<Path>, mut f: F) {
+    let path = file_name.as_ref();
+    for (line_number, line) in BufReader::new(open_input_file(path)).lines().enumerate() {
+        let line = line.unwrap_or_else(|e| panic!("Error reading {:?}: {}", path, e));
+
+        // the dump is a JSON array with an entity in each line
+        let line = line.trim().trim_end_matches(',');
+        if line.is_empty() || line == "[" || line == "]" {
+            continue;
+        }
+
+        match serde_json::from_str(line) {
+            Ok(entity) => f(entity),
+            Err(e) => println!("Skipping line {} of {:?}: {}", line_number + 1, path, e)
+        }
+    }
+}
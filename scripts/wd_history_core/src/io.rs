@@ -0,0 +1,125 @@
+use std::fs::{File, read_dir};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use flate2::read::MultiGzDecoder;
+
+/// Opens an input file, decompressing it if its name ends with .gz or .zst
+pub fn open_input_file(path: &Path) -> Box<dyn Read> {
+    let file = File::open(path).unwrap_or_else(|e| panic!("Could not open file {:?}: {}", path, e));
+    match path.extension() {
+        Some(ext) if ext == "gz" => Box::new(MultiGzDecoder::new(file)),
+        Some(ext) if ext == "zst" => Box::new(zstd::Decoder::new(file)
+            .unwrap_or_else(|e| panic!("Could not read file {:?}: {}", path, e))),
+        _ => Box::new(file)
+    }
+}
+
+/// Whether a file has entities of the edit history: a JSON diff file (.json),
+/// or a JSON Lines file with an entity per line (.jsonl or .ndjson), optionally compressed with gzip (.gz)
+/// or zstd (.zst)
+pub fn is_diff_file(path: &Path) -> bool {
+    let name = get_file_name(path);
+    let name = name.trim_end_matches(".gz").trim_end_matches(".zst");
+    name.ends_with(".json") || name.ends_with(".jsonl") || name.ends_with(".ndjson")
+}
+
+/// Returns the diff files (.json, or .jsonl exported by diff_indexer, optionally compressed) stored in the
+/// given directory, sorted by name
+pub fn get_diff_files(input_dir: impl AsRef<Path>) -> Vec<PathBuf> {
+    let mut paths = read_dir(input_dir.as_ref())
+        .unwrap_or_else(|e| panic!("Could not read input folder {:?}: {}", input_dir.as_ref(), e))
+        .map(|e| e.expect("Error getting files from input folder").path())
+        .filter(|p| is_diff_file(p))
+        .collect::<Vec<PathBuf>>();
+    paths.sort();
+
+    paths
+}
+
+/// Whether a diff file has an entity per line (.jsonl or .ndjson) instead of a JSON array or object
+pub fn is_json_lines(path: &Path) -> bool {
+    let name = get_file_name(path);
+    let name = name.trim_end_matches(".gz").trim_end_matches(".zst");
+    name.ends_with(".jsonl") || name.ends_with(".ndjson")
+}
+
+/// Returns the date of the dump a diff file was computed from, from the name of the dump file it keeps,
+/// e.g. 20211101 for wikidatawiki-20211101-pages-meta-history1.xml-p1p154_0.json
+pub fn get_dump_version(file_name: &str) -> Option<String> {
+    let date = file_name.split("wikidatawiki-").nth(1)?.split('-').next()?;
+    if date.len() == 8 && date.chars().all(|c| c.is_ascii_digit()) {
+        Some(date.to_string())
+    } else {
+        None
+    }
+}
+
+/// Part of a meta history dump, with the range of page ids it holds
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpPart {
+    /// Date of the dump, e.g. 20211101
+    pub date: String,
+    /// Number of the part, e.g. 1 for wikidatawiki-20211101-pages-meta-history1.xml-p1p154.7z
+    pub number: u32,
+    pub first_page_id: u64,
+    pub last_page_id: u64
+}
+
+impl DumpPart {
+    /// Parses the name of a part of a meta history dump, e.g. wikidatawiki-20211101-pages-meta-history1.xml-p1p154
+    /// followed by its extension (.7z, .bz2, .xml...)
+    pub fn parse(file_name: &str) -> Option<DumpPart> {
+        let date = get_dump_version(file_name)?;
+        let part = file_name.strip_prefix(&format!("wikidatawiki-{}-pages-meta-history", date))?;
+        let (number, range) = part.split_once(".xml-p")?;
+        let (first_page_id, last_page_id) = range.split('.').next()?.split_once('p')?;
+        Some(DumpPart {
+            date,
+            number: parse_number(number)?,
+            first_page_id: parse_number(first_page_id)?,
+            last_page_id: parse_number(last_page_id)?
+        })
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(s: &str) -> Option<T> {
+    if s.is_empty() || !s.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+pub fn get_file_name(path: &Path) -> String {
+    path.file_name().unwrap().to_str().unwrap().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_dump_part() {
+        assert_eq!(DumpPart::parse("wikidatawiki-20211101-pages-meta-history1.xml-p1p154.7z"), Some(DumpPart {
+            date: "20211101".to_string(),
+            number: 1,
+            first_page_id: 1,
+            last_page_id: 154
+        }));
+        let part = DumpPart::parse("wikidatawiki-20211101-pages-meta-history27.xml-p67174382p67502430.bz2").unwrap();
+        assert_eq!((part.number, part.first_page_id, part.last_page_id), (27, 67174382, 67502430));
+        assert!(DumpPart::parse("wikidatawiki-20211101-pages-meta-history2.xml-p155p300").is_some());
+    }
+
+    #[test]
+    fn parse_invalid_dump_part() {
+        assert_eq!(DumpPart::parse("wikidatawiki-20211101-md5sums.txt"), None);
+        assert_eq!(DumpPart::parse("wikidatawiki-20211101-pages-articles1.xml-p1p154.bz2"), None);
+        assert_eq!(DumpPart::parse("wikidatawiki-2021-pages-meta-history1.xml-p1p154.7z"), None);
+        assert_eq!(DumpPart::parse("wikidatawiki-20211101-pages-meta-history.xml-p1p154.7z"), None);
+        assert_eq!(DumpPart::parse("wikidatawiki-20211101-pages-meta-history1.xml-p1.7z"), None);
+        assert_eq!(DumpPart::parse("wikidatawiki-20211101-pages-meta-history1.xml-p+1p154.7z"), None);
+        assert_eq!(DumpPart::parse("wikidatawiki-20211101-pages-meta-historyX.xml-p1p154.7z"), None);
+        assert_eq!(DumpPart::parse("wikidatawiki-20211101-pages-meta-history2.xml-p155p300_3.json"), None);
+    }
+}
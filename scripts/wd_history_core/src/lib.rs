@@ -0,0 +1,10 @@
+//! Data model of the Wikidata edit history shared by the tools of this repository: the entities and
+//! revisions of the diff files, how to read them, how to compute them from the meta history dumps and
+//! how to replay them to get the state of an entity at any revision.
+
+pub mod dump;
+pub mod io;
+pub mod model;
+pub mod op_path;
+pub mod replay;
+pub mod shard;
@@ -0,0 +1,107 @@
+use json_patch::{AddOperation, CopyOperation, MoveOperation, Patch, PatchOperation, RemoveOperation,
+                 ReplaceOperation, TestOperation};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+/// Version of the format of the diff files, written in their header (see `shard::ShardHeader`). Increased
+/// whenever a change prevents previous readers from reading the files. Versions:
+/// 1. JSON array of entities, without header.
+/// 2. Header with the format version and the tool, dump and options that wrote the file, followed by the entities.
+pub const FORMAT_VERSION: u32 = 2;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct WikidataRevision {
+    pub id: u64,
+    pub parent_id: u64,
+    /// ISO 8601 timestamp in UTC, e.g. 2021-11-01T00:00:00Z
+    pub timestamp: String,
+    pub username: String,
+    pub comment: String,
+    /// JSON Patch from the entity JSON of the parent revision (or an empty object for the first revision)
+    /// to the entity JSON of this revision
+    pub entity_diff: Option<Vec::<WikidataOp>>
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct WikidataItem {
+    pub id: u64,
+    pub entity_id: String,
+    /// Entity JSON after the last revision
+    pub entity_json: Value,
+    pub revisions: Vec::<WikidataRevision>
+}
+
+/// JSON Patch operation types
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PatchOp {
+    Add,
+    Remove,
+    Replace,
+    Move,
+    Copy,
+    Test
+}
+
+impl PatchOp {
+    pub const NAMES: [&'static str; 6] = ["add", "remove", "replace", "move", "copy", "test"];
+
+    pub fn name(&self) -> &'static str {
+        PatchOp::NAMES[*self as usize]
+    }
+}
+
+/// JSON Patch operation, serialized like the operations of json_patch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WikidataOp {
+    pub op: PatchOp,
+    pub path: String,
+    /// Source of move and copy operations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// Value of add, replace and test operations, which may be null
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "deserialize_value")]
+    pub value: Option<Value>
+}
+
+/// Keeps null values, which serde would read as None
+fn deserialize_value<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+impl WikidataOp {
+    pub fn from_patch_operation(op: &PatchOperation) -> WikidataOp {
+        let (op, path, from, value) = match op {
+            PatchOperation::Add(op) => (PatchOp::Add, &op.path, None, Some(&op.value)),
+            PatchOperation::Remove(op) => (PatchOp::Remove, &op.path, None, None),
+            PatchOperation::Replace(op) => (PatchOp::Replace, &op.path, None, Some(&op.value)),
+            PatchOperation::Move(op) => (PatchOp::Move, &op.path, Some(&op.from), None),
+            PatchOperation::Copy(op) => (PatchOp::Copy, &op.path, Some(&op.from), None),
+            PatchOperation::Test(op) => (PatchOp::Test, &op.path, None, Some(&op.value))
+        };
+        WikidataOp { op, path: path.clone(), from: from.cloned(), value: value.cloned() }
+    }
+
+    pub fn to_patch_operation(&self) -> PatchOperation {
+        let path = self.path.clone();
+        let from = self.from.clone().unwrap_or_default();
+        let value = self.value.clone().unwrap_or(Value::Null);
+        match self.op {
+            PatchOp::Add => PatchOperation::Add(AddOperation { path, value }),
+            PatchOp::Remove => PatchOperation::Remove(RemoveOperation { path }),
+            PatchOp::Replace => PatchOperation::Replace(ReplaceOperation { path, value }),
+            PatchOp::Move => PatchOperation::Move(MoveOperation { from, path }),
+            PatchOp::Copy => PatchOperation::Copy(CopyOperation { from, path }),
+            PatchOp::Test => PatchOperation::Test(TestOperation { path, value })
+        }
+    }
+}
+
+/// Converts a JSON Patch, e.g. computed with json_patch::diff, to the operations of a revision
+pub fn from_patch(patch: &Patch) -> Vec<WikidataOp> {
+    patch.0.iter().map(WikidataOp::from_patch_operation).collect()
+}
+
+pub fn to_patch(ops: &[WikidataOp]) -> Patch {
+    Patch(ops.iter().map(WikidataOp::to_patch_operation).collect())
+}
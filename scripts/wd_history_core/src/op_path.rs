@@ -0,0 +1,128 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Parts of the entity edited by an op, parsed from its JSON Pointer path, e.g.
+/// `/claims/P31/0/qualifiers/P580/0` or `/labels/en/value`. Only the fields present in the path are set.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OpPath {
+    /// First level of the entity JSON: claims, labels, descriptions, aliases, sitelinks...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section: Option<String>,
+    /// Property of the edited statements
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub property: Option<String>,
+    /// Position of the edited statement in the list of statements of the property
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statement_index: Option<u32>,
+    /// GUID of the edited statement, only known when the op value is the whole statement
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub statement_guid: Option<String>,
+    /// Part of the edited statement: mainsnak, qualifiers, references, rank...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subpart: Option<String>,
+    /// Language of the edited label, description or alias
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Site of the edited sitelink, e.g. enwiki
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub site: Option<String>
+}
+
+impl OpPath {
+    pub fn parse(path: &str, value: Option<&Value>) -> OpPath {
+        let tokens = path.split('/').skip(1).map(unescape).collect::<Vec<String>>();
+        let mut op_path = OpPath { section: tokens.first().cloned(), ..OpPath::default() };
+
+        match op_path.section.as_deref() {
+            Some("claims") => {
+                op_path.property = tokens.get(1).cloned();
+                op_path.statement_index = tokens.get(2).and_then(|t| t.parse().ok());
+                op_path.subpart = tokens.get(3).cloned();
+
+                // only when a single statement is added or replaced as a whole. Ops on a whole property
+                // (e.g. /claims/P31) have a list of statements as value, which may have several GUIDs.
+                op_path.statement_guid = match (tokens.len(), value) {
+                    (3, Some(statement)) => statement.get("id").and_then(Value::as_str).map(str::to_string),
+                    _ => None
+                };
+            },
+            Some("labels") | Some("descriptions") | Some("aliases") => op_path.language = tokens.get(1).cloned(),
+            Some("sitelinks") => op_path.site = tokens.get(1).cloned(),
+            _ => ()
+        }
+
+        op_path
+    }
+}
+
+/// Unescapes a JSON Pointer reference token
+fn unescape(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn parse_claims_paths() {
+        let statement = json!({"id": "Q42$F078E5B3-F9A8-480E-B7AC-D97778CBBEF9", "mainsnak": {}, "rank": "normal"});
+        assert_eq!(OpPath::parse("/claims/P31/0", Some(&statement)), OpPath {
+            section: Some("claims".to_string()),
+            property: Some("P31".to_string()),
+            statement_index: Some(0),
+            statement_guid: Some("Q42$F078E5B3-F9A8-480E-B7AC-D97778CBBEF9".to_string()),
+            ..OpPath::default()
+        });
+        // removed statements have no value
+        assert_eq!(OpPath::parse("/claims/P31/1", None).statement_guid, None);
+
+        assert_eq!(OpPath::parse("/claims/P31/2/qualifiers/P580/0", Some(&json!({"snaktype": "value"}))), OpPath {
+            section: Some("claims".to_string()),
+            property: Some("P31".to_string()),
+            statement_index: Some(2),
+            subpart: Some("qualifiers".to_string()),
+            ..OpPath::default()
+        });
+
+        assert_eq!(OpPath::parse("/claims/P31", Some(&json!([statement]))), OpPath {
+            section: Some("claims".to_string()),
+            property: Some("P31".to_string()),
+            ..OpPath::default()
+        });
+        assert_eq!(OpPath::parse("/claims", Some(&json!({}))).property, None);
+    }
+
+    #[test]
+    fn parse_terms_paths() {
+        for section in ["labels", "descriptions", "aliases"] {
+            assert_eq!(OpPath::parse(&format!("/{}/en", section), None), OpPath {
+                section: Some(section.to_string()),
+                language: Some("en".to_string()),
+                ..OpPath::default()
+            });
+        }
+        assert_eq!(OpPath::parse("/labels/en/value", None).language.as_deref(), Some("en"));
+        assert_eq!(OpPath::parse("/aliases/de/1", None).language.as_deref(), Some("de"));
+        assert_eq!(OpPath::parse("/aliases", None).language, None);
+    }
+
+    #[test]
+    fn parse_sitelinks_paths() {
+        assert_eq!(OpPath::parse("/sitelinks/enwiki/title", None), OpPath {
+            section: Some("sitelinks".to_string()),
+            site: Some("enwiki".to_string()),
+            ..OpPath::default()
+        });
+        // JSON Pointer escapes
+        assert_eq!(OpPath::parse("/sitelinks/a~1b~0c", None).site.as_deref(), Some("a/b~c"));
+    }
+
+    #[test]
+    fn parse_root_path() {
+        assert_eq!(OpPath::parse("", Some(&json!({"claims": {}}))), OpPath::default());
+        assert_eq!(OpPath::parse("/id", None), OpPath { section: Some("id".to_string()), ..OpPath::default() });
+    }
+}
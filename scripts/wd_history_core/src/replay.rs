@@ -0,0 +1,85 @@
+use crate::model::{to_patch, PatchOp, WikidataItem, WikidataOp, WikidataRevision};
+
+use json_patch::patch;
+use serde_json::{Map, Value};
+
+/// Sections of an entity that the dumps serialize as an empty array instead of an empty object
+const OBJECT_SECTIONS: [&str; 5] = ["/claims", "/labels", "/descriptions", "/aliases", "/sitelinks"];
+
+/// Replaces empty arrays assigned to the top level sections of an entity with empty objects,
+/// so the following operations of the history (e.g. adding `/claims/P31`) can be applied.
+fn normalize_operation(op: &WikidataOp) -> WikidataOp {
+    let mut op = op.clone();
+    if matches!(op.op, PatchOp::Add | PatchOp::Replace) && OBJECT_SECTIONS.contains(&op.path.as_str())
+        && op.value == Some(Value::Array(Vec::new())) {
+        op.value = Some(Value::Object(Map::new()));
+    }
+
+    op
+}
+
+/// Applies the diff of a revision to the state of the entity in the previous revision
+pub fn apply_revision(entity_json: &mut Value, entity_id: &str, revision: &WikidataRevision) {
+    if let Some(diff) = &revision.entity_diff {
+        let diff = to_patch(&diff.iter().map(normalize_operation).collect::<Vec<WikidataOp>>());
+        if let Err(e) = patch(entity_json, &diff) {
+            println!("Could not apply revision {} of entity {}: {}", revision.id, entity_id, e);
+        }
+    }
+}
+
+/// Returns the state of an entity after its last revision made at or before the given timestamp,
+/// or its final state if no timestamp is given. Returns None if the entity did not exist yet.
+pub fn get_entity_at(item: &WikidataItem, timestamp: Option<&str>) -> Option<Value> {
+    let timestamp = match timestamp {
+        Some(t) => t,
+        None => return Some(item.entity_json.clone())
+    };
+
+    // each diff is computed against the previous revision, so only a prefix of the history can be replayed.
+    // Timestamps are stored as ISO 8601 strings in UTC, so they can be compared as strings
+    let mut revisions = item.revisions.iter().take_while(|r| r.timestamp.as_str() <= timestamp).peekable();
+    revisions.peek()?;
+
+    let mut entity_json = Value::Object(Map::new());
+    for revision in revisions {
+        apply_revision(&mut entity_json, &item.entity_id, revision);
+    }
+
+    Some(entity_json)
+}
+
+/// Parses a timestamp given as argument into the format of the revision timestamps (e.g. 2020-01-01T00:00:00Z),
+/// so both can be compared as strings. Accepts a date (at midnight) or a date and time in UTC.
+pub fn parse_timestamp(timestamp: &str) -> Result<String, String> {
+    let timestamp = timestamp.trim();
+    let (date, time) = timestamp.split_once('T').unwrap_or((timestamp, "00:00:00"));
+    let time = time.strip_suffix('Z').unwrap_or(time);
+
+    let is_valid = |value: &str, separator: char, lengths: [usize; 3]| {
+        let fields = value.split(separator).collect::<Vec<&str>>();
+        fields.len() == 3
+            && fields.iter().zip(lengths).all(|(f, len)| f.len() == len && f.chars().all(|c| c.is_ascii_digit()))
+    };
+    if !is_valid(date, '-', [4, 2, 2]) || !is_valid(time, ':', [2, 2, 2]) {
+        return Err(format!("Invalid timestamp {}, it should be a date or a time in UTC like 2020-01-01 or \
+                            2020-01-01T00:00:00Z", timestamp));
+    }
+
+    Ok(format!("{}T{}Z", date, time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_timestamps() {
+        assert_eq!(parse_timestamp("2020-01-01").unwrap(), "2020-01-01T00:00:00Z");
+        assert_eq!(parse_timestamp("2020-01-01T12:30:00").unwrap(), "2020-01-01T12:30:00Z");
+        assert_eq!(parse_timestamp("2020-01-01T12:30:00Z").unwrap(), "2020-01-01T12:30:00Z");
+        for timestamp in ["2020-1-1", "2020-01-01T12:30", "2020-01-01T12:30:00+01:00", "01/01/2020", ""] {
+            assert!(parse_timestamp(timestamp).is_err(), "{} should be invalid", timestamp);
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use crate::io::{is_json_lines, open_input_file};
+use crate::model::{WikidataItem, FORMAT_VERSION};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use serde_json::Value;
+
+/// Metadata written at the start of every diff file. JSON diff files are an object with the header and an
+/// array of entities (`{"header": {...}, "entities": [...]}`), and JSON Lines files start with a
+/// `{"header": {...}}` line followed by an entity per line. See schema/diff_file.schema.json.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShardHeader {
+    /// Version of the format of the file, see `model::FORMAT_VERSION`
+    pub format_version: u32,
+    /// Tool that wrote the file, e.g. wd_diff_calculator, and its version
+    #[serde(default)]
+    pub producer: String,
+    #[serde(default)]
+    pub producer_version: String,
+    /// Date of the dump the entities come from, e.g. 20211101
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dump_date: Option<String>,
+    /// Dump file (or database) the entities were read from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_file: Option<String>,
+    /// Options the producer was run with
+    #[serde(default)]
+    pub options: BTreeMap<String, Value>
+}
+
+impl ShardHeader {
+    /// Header of a file written now by the given tool
+    pub fn new(producer: &str, producer_version: &str) -> ShardHeader {
+        ShardHeader {
+            format_version: FORMAT_VERSION,
+            producer: producer.to_string(),
+            producer_version: producer_version.to_string(),
+            ..ShardHeader::default()
+        }
+    }
+
+    /// Header of the files written before the format had a version, which are a JSON array of entities
+    fn unversioned() -> ShardHeader {
+        ShardHeader { format_version: 1, ..ShardHeader::default() }
+    }
+}
+
+/// Error reading a diff file
+pub enum ShardError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, String),
+    /// The file has a newer format version than the ones this build reads
+    UnsupportedVersion { path: PathBuf, format_version: u64, producer: String }
+}
+
+impl fmt::Display for ShardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShardError::Io(path, e) => write!(f, "Error reading file {:?}: {}", path, e),
+            ShardError::Parse(path, e) => write!(f, "Error parsing file {:?}: {}", path, e),
+            ShardError::UnsupportedVersion { path, format_version, producer } =>
+                write!(f, "{:?} was written by {} with format version {}, but this build only reads versions up to {}. \
+                           Update the tools to read it.", path, producer, format_version, FORMAT_VERSION)
+        }
+    }
+}
+
+// shown by the binaries whose main returns the error
+impl fmt::Debug for ShardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for ShardError {}
+
+/// A diff file with the JSON of its entities, not parsed yet
+pub struct RawShard {
+    pub header: ShardHeader,
+    pub items: Vec<Box<RawValue>>
+}
+
+#[derive(Deserialize)]
+struct VersionedShard {
+    header: Value,
+    entities: Vec<Box<RawValue>>
+}
+
+#[derive(Serialize)]
+struct VersionedShardRef<'a> {
+    header: &'a ShardHeader,
+    entities: &'a [WikidataItem]
+}
+
+#[derive(Serialize)]
+struct HeaderLine<'a> {
+    header: &'a ShardHeader
+}
+
+/// First line of a JSON Lines diff file. Entity lines have other fields, so they are not mistaken for it.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReadHeaderLine {
+    header: Value
+}
+
+/// Reads the header of a diff file, checking that its format version can be read. The version is checked
+/// before reading the rest of the header, whose fields may be different in newer versions.
+fn read_header(path: &Path, header: Value) -> Result<ShardHeader, ShardError> {
+    let format_version = header.get("format_version").and_then(Value::as_u64)
+        .ok_or_else(|| ShardError::Parse(path.to_path_buf(), "the header has no format_version".to_string()))?;
+    if format_version > FORMAT_VERSION as u64 {
+        let producer = header.get("producer").and_then(Value::as_str).unwrap_or("an unknown tool");
+        let producer_version = header.get("producer_version").and_then(Value::as_str).unwrap_or_default();
+        return Err(ShardError::UnsupportedVersion {
+            path: path.to_path_buf(),
+            format_version,
+            producer: format!("{} {}", producer, producer_version).trim_end().to_string()
+        });
+    }
+    serde_json::from_value(header).map_err(|e| ShardError::Parse(path.to_path_buf(), format!("invalid header: {}", e)))
+}
+
+/// Returns the first byte of the reader that is not whitespace, without consuming it
+fn peek_non_whitespace(reader: &mut impl BufRead, path: &Path) -> Result<Option<u8>, ShardError> {
+    loop {
+        let buf = reader.fill_buf().map_err(|e| ShardError::Io(path.to_path_buf(), e))?;
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        match buf.iter().position(|b| !b.is_ascii_whitespace()) {
+            Some(i) => {
+                let byte = buf[i];
+                reader.consume(i);
+                return Ok(Some(byte));
+            },
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+/// Reads the header and the JSON of the entities of a diff file, without parsing the entities. Files of
+/// every format version up to `FORMAT_VERSION` are accepted, and files of newer versions are rejected.
+pub fn read_raw_shard(path: &Path) -> Result<RawShard, ShardError> {
+    let parse_error = |e: serde_json::Error| ShardError::Parse(path.to_path_buf(), e.to_string());
+    let mut reader = BufReader::new(open_input_file(path));
+    if !is_json_lines(path) {
+        return match peek_non_whitespace(&mut reader, path)? {
+            Some(b'{') => {
+                let shard: VersionedShard = serde_json::from_reader(reader).map_err(parse_error)?;
+                Ok(RawShard { header: read_header(path, shard.header)?, items: shard.entities })
+            },
+            _ => {
+                let items = serde_json::from_reader(reader).map_err(parse_error)?;
+                Ok(RawShard { header: ShardHeader::unversioned(), items })
+            }
+        };
+    }
+
+    let mut header = None;
+    let mut items = Vec::new();
+    for (line_number, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| ShardError::Io(path.to_path_buf(), e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // only the first line can be the header
+        if header.is_none() && items.is_empty() {
+            if let Ok(header_line) = serde_json::from_str::<ReadHeaderLine>(&line) {
+                header = Some(read_header(path, header_line.header)?);
+                continue;
+            }
+        }
+        items.push(RawValue::from_string(line)
+            .map_err(|e| ShardError::Parse(path.to_path_buf(), format!("line {}: {}", line_number + 1, e)))?);
+    }
+
+    Ok(RawShard { header: header.unwrap_or_else(ShardHeader::unversioned), items })
+}
+
+/// Reads the header and the entities of a diff file
+pub fn read_shard(path: &Path) -> Result<(ShardHeader, Vec<WikidataItem>), ShardError> {
+    let shard = read_raw_shard(path)?;
+    let items = shard.items.iter()
+        .map(|raw_item| serde_json::from_str(raw_item.get())
+            .map_err(|e| ShardError::Parse(path.to_path_buf(), format!("invalid entity: {}", e))))
+        .collect::<Result<Vec<WikidataItem>, ShardError>>()?;
+    Ok((shard.header, items))
+}
+
+/// Reads the entities of a diff file
+pub fn read_items(path: &Path) -> Result<Vec<WikidataItem>, ShardError> {
+    Ok(read_shard(path)?.1)
+}
+
+/// Writes a JSON diff file with the header and the entities
+pub fn write_shard<W: Write>(writer: W, header: &ShardHeader, items: &[WikidataItem]) -> serde_json::Result<()> {
+    serde_json::to_writer(writer, &VersionedShardRef { header, entities: items })
+}
+
+/// Writes the header line of a JSON Lines diff file, which is followed by an entity per line
+pub fn write_header_line<W: Write>(mut writer: W, header: &ShardHeader) -> std::io::Result<()> {
+    serde_json::to_writer(&mut writer, &HeaderLine { header })?;
+    writer.write_all(b"\n")
+}
@@ -0,0 +1,86 @@
+use clap::App;
+use diff_indexer::config::{Config, ElasticsearchConfig, MongoConfig, PostgresConfig};
+use serde::Deserialize;
+use toml::value::{Table, Value};
+
+/// Contents of the TOML configuration file shared by every subcommand. The connection settings are the
+/// same as the ones of diff_indexer, and the `download`, `diff` and `index` tables hold the options of each
+/// step of the `run` subcommand, with the names of the command line options (e.g. `input_dir`).
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WdhistConfig {
+    #[serde(default)]
+    pub mongo: MongoConfig,
+    #[serde(default)]
+    pub postgres: PostgresConfig,
+    #[serde(default)]
+    pub elasticsearch: ElasticsearchConfig,
+    #[serde(default)]
+    pub download: Table,
+    #[serde(default)]
+    pub diff: Table,
+    #[serde(default)]
+    pub index: Table
+}
+
+impl WdhistConfig {
+    pub fn load(config_file: Option<&str>) -> WdhistConfig {
+        match config_file {
+            Some(path) => {
+                let contents = std::fs::read_to_string(path)
+                    .unwrap_or_else(|e| panic!("Could not read config file {}: {}", path, e));
+                toml::from_str(&contents).unwrap_or_else(|e| panic!("Error parsing config file {}: {}", path, e))
+            },
+            None => WdhistConfig::default()
+        }
+    }
+
+    /// Splits the connection settings, used by diff_indexer, from the options of the steps
+    pub fn split(self) -> (Config, Steps) {
+        let config = Config { mongo: self.mongo, postgres: self.postgres, elasticsearch: self.elasticsearch };
+        (config, Steps { download: self.download, diff: self.diff, index: self.index })
+    }
+}
+
+/// Options of each step of the `run` subcommand
+#[derive(Debug, Default)]
+pub struct Steps {
+    pub download: Table,
+    pub diff: Table,
+    pub index: Table
+}
+
+/// Parses the options of a step from its table of the config file, as if they were given in the command
+/// line of its subcommand. Invalid options exit with the same message and status as in the command line.
+pub fn parse_step<T: clap::Args>(name: &str, table: &Table) -> T {
+    let app = T::augment_args(App::new(name));
+    let matches = app.try_get_matches_from(get_step_args(name, table)).unwrap_or_else(|e| e.exit());
+    T::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+}
+
+/// Converts a table of the config file to command line arguments: `key = value` becomes `--key value`,
+/// `key = true` becomes `--key` and arrays are joined with commas
+fn get_step_args(name: &str, table: &Table) -> Vec<String> {
+    let mut args = vec![name.to_string()];
+    for (key, value) in table {
+        let option = format!("--{}", key.replace('_', "-"));
+        match value {
+            Value::Boolean(true) => args.push(option),
+            Value::Boolean(false) => {},
+            Value::String(s) => args.extend([option, s.clone()]),
+            Value::Array(values) => {
+                let values = values.iter().map(get_step_value).collect::<Vec<String>>();
+                args.extend([option, values.join(",")]);
+            },
+            value => args.extend([option, get_step_value(value)])
+        }
+    }
+    args
+}
+
+fn get_step_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string()
+    }
+}
@@ -0,0 +1,379 @@
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, remove_file, rename, File, OpenOptions};
+use std::io::{copy, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use md5::Md5;
+use reqwest::header::RANGE;
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use wd_history_core::io::DumpPart;
+
+const DEFAULT_MIRROR: &str = "https://dumps.wikimedia.org/wikidatawiki";
+
+/// Name of the job of the meta history dump in dumpstatus.json
+const META_HISTORY_JOB: &str = "metahistory7zdump";
+
+pub type DownloadError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(clap::Args, Debug)]
+pub struct DownloadArgs {
+    /// Date of the dump release, e.g. 20211101
+    #[clap(long, default_value = "20211101")]
+    date: String,
+
+    /// Directory where the dump files are saved
+    #[clap(short, long)]
+    output_dir: String,
+
+    /// Base URL of the dumps, or local directory with the same layout (a directory for each dump date with
+    /// its dumpstatus.json or md5sums file and the dump files)
+    #[clap(long, default_value = DEFAULT_MIRROR)]
+    mirror: String,
+
+    /// Only download these parts of the dump (comma separated), as part numbers or ranges of them, e.g. 1-3,27.
+    /// Part 1 is wikidatawiki-20211101-pages-meta-history1.xml-p1p154.7z and its following files.
+    #[clap(long, use_delimiter = true)]
+    parts: Vec<String>,
+
+    /// Verify the checksums of the files already downloaded with the expected size too, downloading them again
+    /// if they don't match. Files whose size is unknown (dumps without dumpstatus.json) are always verified.
+    #[clap(long)]
+    verify_existing: bool
+}
+
+/// A file of the dump, with its size and checksums if they are known
+#[derive(Debug, Default, Clone, Deserialize)]
+struct DumpFile {
+    #[serde(default)]
+    size: Option<u64>,
+    #[serde(default)]
+    md5: Option<String>,
+    #[serde(default)]
+    sha1: Option<String>
+}
+
+#[derive(Deserialize)]
+struct DumpStatus {
+    jobs: BTreeMap<String, DumpJob>
+}
+
+#[derive(Deserialize)]
+struct DumpJob {
+    status: String,
+    #[serde(default)]
+    files: BTreeMap<String, DumpFile>
+}
+
+/// Where the dump files are read from: a mirror of dumps.wikimedia.org or a local directory
+enum DumpSource {
+    Http { client: Client, dump_url: String },
+    Local { dump_dir: PathBuf }
+}
+
+impl DumpSource {
+    fn new(mirror: &str, date: &str) -> DumpSource {
+        if mirror.starts_with("http://") || mirror.starts_with("https://") {
+            DumpSource::Http { client: Client::new(), dump_url: format!("{}/{}", mirror.trim_end_matches('/'), date) }
+        } else {
+            let dir = mirror.strip_prefix("file://").unwrap_or(mirror);
+            DumpSource::Local { dump_dir: Path::new(dir).join(date) }
+        }
+    }
+
+    fn location(&self) -> String {
+        match self {
+            DumpSource::Http { dump_url, .. } => dump_url.clone(),
+            DumpSource::Local { dump_dir } => dump_dir.display().to_string()
+        }
+    }
+
+    /// Reads a small file of the dump, such as dumpstatus.json, or None if it doesn't exist
+    async fn read_text(&self, file_name: &str) -> Result<Option<String>, DownloadError> {
+        match self {
+            DumpSource::Http { client, dump_url } => {
+                let response = client.get(format!("{}/{}", dump_url, file_name)).send().await?;
+                if response.status() == StatusCode::NOT_FOUND {
+                    return Ok(None);
+                }
+                Ok(Some(response.error_for_status()?.text().await?))
+            },
+            DumpSource::Local { dump_dir } => {
+                let path = dump_dir.join(file_name);
+                if !path.exists() {
+                    return Ok(None);
+                }
+                Ok(Some(std::fs::read_to_string(&path)?))
+            }
+        }
+    }
+
+    /// Appends the rest of a dump file to its partial download, starting at its current length
+    async fn fetch(&self, file_name: &str, part_path: &Path, pb: &ProgressBar) -> Result<(), DownloadError> {
+        let mut part_file = OpenOptions::new().create(true).append(true).open(part_path)?;
+        let offset = part_file.metadata()?.len();
+        match self {
+            DumpSource::Http { client, dump_url } => {
+                let mut request = client.get(format!("{}/{}", dump_url, file_name));
+                if offset > 0 {
+                    request = request.header(RANGE, format!("bytes={}-", offset));
+                }
+                let mut response = request.send().await?;
+                if offset > 0 && response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+                    // the partial download is already complete
+                    return Ok(());
+                }
+                let mut position = offset;
+                if response.status() != StatusCode::PARTIAL_CONTENT {
+                    // the server ignored the range and sends the whole file
+                    response = response.error_for_status()?;
+                    part_file.set_len(0)?;
+                    position = 0;
+                }
+                pb.set_length(position + response.content_length().unwrap_or(0));
+                pb.set_position(position);
+                while let Some(chunk) = response.chunk().await? {
+                    part_file.write_all(&chunk)?;
+                    pb.inc(chunk.len() as u64);
+                }
+            },
+            DumpSource::Local { dump_dir } => {
+                let mut file = File::open(dump_dir.join(file_name))?;
+                file.seek(SeekFrom::Start(offset))?;
+                pb.set_length(file.metadata()?.len());
+                pb.set_position(offset);
+                copy(&mut file, &mut pb.wrap_write(&mut part_file))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Downloads the meta history dump files of a dump release which are not in the output directory yet,
+/// resuming the partial downloads and verifying their checksums
+pub async fn download(args: &DownloadArgs) -> Result<(), DownloadError> {
+    let source = DumpSource::new(&args.mirror, &args.date);
+    let parts = parse_part_ranges(&args.parts)?;
+    let files = get_dump_files(&source, &args.date).await?.into_iter()
+        .filter(|(file_name, _)| {
+            let number = DumpPart::parse(file_name).map(|part| part.number).unwrap_or_default();
+            parts.is_empty() || parts.iter().any(|(first, last)| (*first..=*last).contains(&number))
+        })
+        .collect::<BTreeMap<String, DumpFile>>();
+    println!("Downloading {} meta history dump files from {}", files.len(), source.location());
+
+    create_dir_all(&args.output_dir)
+        .unwrap_or_else(|e| panic!("Could not create output folder {}: {}", args.output_dir, e));
+    for (file_name, dump_file) in files.iter() {
+        let path = Path::new(&args.output_dir).join(file_name);
+        if path.exists() {
+            let size = path.metadata()?.len();
+            if dump_file.size.is_some_and(|expected| size < expected) {
+                // e.g. a partial download of wget -c, used by previous versions
+                println!("{} is incomplete, resuming its download", file_name);
+                rename(&path, path.with_extension("7z.part"))?;
+            } else if (args.verify_existing || dump_file.size.is_none_or(|expected| size > expected))
+                && !verify_checksums(&path, dump_file)? {
+                println!("{} does not match its checksums, downloading it again", file_name);
+                remove_file(&path)?;
+            } else {
+                println!("Skipping {}, already downloaded", file_name);
+                continue;
+            }
+        }
+
+        download_file(&source, file_name, dump_file, &path).await?;
+    }
+    Ok(())
+}
+
+/// Files of the meta history dump and their checksums, read from dumpstatus.json, or from the md5sums and
+/// sha1sums files of the dump if it doesn't exist
+async fn get_dump_files(source: &DumpSource, date: &str) -> Result<BTreeMap<String, DumpFile>, DownloadError> {
+    if let Some(contents) = source.read_text("dumpstatus.json").await? {
+        let status: DumpStatus = serde_json::from_str(&contents)
+            .map_err(|e| format!("Error parsing dumpstatus.json of {}: {}", source.location(), e))?;
+        let job = status.jobs.get(META_HISTORY_JOB)
+            .ok_or_else(|| format!("The dump of {} has no {} job", date, META_HISTORY_JOB))?;
+        if job.status != "done" {
+            println!("The meta history dump of {} is not finished (status: {}), only its listed files are downloaded",
+                     date, job.status);
+        }
+        return Ok(job.files.iter()
+            .filter(|(file_name, _)| DumpPart::parse(file_name).is_some())
+            .map(|(file_name, dump_file)| (file_name.clone(), dump_file.clone()))
+            .collect());
+    }
+
+    let md5_sums = source.read_text(&format!("wikidatawiki-{}-md5sums.txt", date)).await?
+        .ok_or_else(|| format!("Neither dumpstatus.json nor the md5sums file were found in {}", source.location()))?;
+    let mut files = BTreeMap::new();
+    for (file_name, md5) in parse_checksums(&md5_sums) {
+        files.insert(file_name, DumpFile { md5: Some(md5), ..DumpFile::default() });
+    }
+    if let Some(sha1_sums) = source.read_text(&format!("wikidatawiki-{}-sha1sums.txt", date)).await? {
+        for (file_name, sha1) in parse_checksums(&sha1_sums) {
+            if let Some(dump_file) = files.get_mut(&file_name) {
+                dump_file.sha1 = Some(sha1);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Reads the lines of a checksums file (`<checksum>  <file name>`) which belong to meta history dump files
+fn parse_checksums(contents: &str) -> Vec<(String, String)> {
+    contents.lines()
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .map(|(checksum, file_name)| (file_name.trim().to_string(), checksum.to_lowercase()))
+        .filter(|(file_name, _)| file_name.ends_with(".7z") && DumpPart::parse(file_name).is_some())
+        .collect()
+}
+
+/// Parses part numbers and ranges of them (e.g. 1-3) into inclusive ranges
+fn parse_part_ranges(parts: &[String]) -> Result<Vec<(u32, u32)>, DownloadError> {
+    parts.iter()
+        .map(|part| {
+            let (first, last) = part.split_once('-').unwrap_or((part, part));
+            match (first.trim().parse(), last.trim().parse()) {
+                (Ok(first), Ok(last)) if first <= last => Ok((first, last)),
+                _ => Err(format!("Invalid part range: {}", part).into())
+            }
+        })
+        .collect()
+}
+
+/// Downloads a file into a `.part` file, resuming it if it exists, and renames it once complete and verified so
+/// interrupted or corrupted downloads are not mistaken for complete ones
+async fn download_file(source: &DumpSource, file_name: &str, dump_file: &DumpFile,
+                       path: &Path) -> Result<(), DownloadError> {
+    let style = ProgressStyle::default_bar()
+        .template("[{elapsed_precise}] {bar:40.cyan/blue} {bytes}/{total_bytes} ({bytes_per_sec}, ETA {eta}) {msg}")
+        .progress_chars("##-");
+    let pb = ProgressBar::new(dump_file.size.unwrap_or(0));
+    pb.set_style(style);
+    pb.set_message(file_name.to_string());
+
+    let part_path = path.with_extension("7z.part");
+    if part_path.exists() {
+        pb.println(format!("Resuming the download of {}", file_name));
+    }
+    source.fetch(file_name, &part_path, &pb).await?;
+    pb.finish();
+
+    if !verify_checksums(&part_path, dump_file)? {
+        remove_file(&part_path)?;
+        return Err(format!("{} does not match its checksums. It was deleted, so it is downloaded again in the next run",
+                           file_name).into());
+    }
+    rename(&part_path, path)?;
+    Ok(())
+}
+
+/// Whether the md5 and sha1 checksums of a file, if known, match its contents
+fn verify_checksums(path: &Path, dump_file: &DumpFile) -> Result<bool, DownloadError> {
+    if dump_file.md5.is_none() && dump_file.sha1.is_none() {
+        println!("No checksums for {:?}, it is not verified", path);
+        return Ok(true);
+    }
+
+    let mut file = File::open(path)?;
+    let mut md5 = Md5::new();
+    let mut sha1 = Sha1::new();
+    let mut buffer = vec![0; 1 << 20];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        md5.update(&buffer[..n]);
+        sha1.update(&buffer[..n]);
+    }
+
+    let md5 = format!("{:x}", md5.finalize());
+    let sha1 = format!("{:x}", sha1.finalize());
+    Ok(dump_file.md5.as_ref().is_none_or(|expected| *expected == md5)
+        && dump_file.sha1.as_ref().is_none_or(|expected| *expected == sha1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    fn part_name(number: u32) -> String {
+        format!("wikidatawiki-20211101-pages-meta-history{}.xml-p{}p{}.7z", number, number * 10 - 9, number * 10)
+    }
+
+    #[test]
+    fn parse_meta_history_checksums() {
+        let contents = format!("0123ABCD  {}\n\
+                                4567ef  wikidatawiki-20211101-md5sums.txt\n\
+                                89ab  wikidatawiki-20211101-pages-meta-history1.xml-p1p10.bz2\n\
+                                \n\
+                                cdef  {}\n", part_name(1), part_name(2));
+        assert_eq!(parse_checksums(&contents), vec![
+            (part_name(1), "0123abcd".to_string()),
+            (part_name(2), "cdef".to_string())
+        ]);
+    }
+
+    #[test]
+    fn parse_valid_part_ranges() {
+        let parts = ["1-3", "27", " 5 - 5 "].map(String::from);
+        assert_eq!(parse_part_ranges(&parts).unwrap(), vec![(1, 3), (27, 27), (5, 5)]);
+        assert!(parse_part_ranges(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn parse_invalid_part_ranges() {
+        for part in ["3-1", "a", "1-", "-2", "1-2-3", ""] {
+            assert!(parse_part_ranges(&[part.to_string()]).is_err(), "{} should be invalid", part);
+        }
+    }
+
+    #[tokio::test]
+    async fn download_from_local_mirror() {
+        let work_dir = std::env::temp_dir().join(format!("wdhist_download_test_{}", std::process::id()));
+        let dump_dir = work_dir.join("mirror").join("20211101");
+        let output_dir = work_dir.join("output");
+        create_dir_all(&dump_dir).unwrap();
+        create_dir_all(&output_dir).unwrap();
+
+        let contents = (1..=3).map(|number| format!("contents of part {}", number).repeat(100)).collect::<Vec<_>>();
+        let mut files = serde_json::Map::new();
+        for (number, part_contents) in (1..=3).zip(contents.iter()) {
+            std::fs::write(dump_dir.join(part_name(number)), part_contents).unwrap();
+            // the checksum of part 3 is wrong
+            let md5 = if number == 3 { "0".repeat(32) } else { format!("{:x}", Md5::digest(part_contents)) };
+            files.insert(part_name(number), json!({"size": part_contents.len(), "md5": md5}));
+        }
+        let status = json!({"jobs": {META_HISTORY_JOB: {"status": "done", "files": files}}});
+        std::fs::write(dump_dir.join("dumpstatus.json"), status.to_string()).unwrap();
+
+        // part 1 was interrupted, part 2 is larger than expected
+        std::fs::write(output_dir.join(part_name(1)).with_extension("7z.part"), &contents[0][..100]).unwrap();
+        std::fs::write(output_dir.join(part_name(2)), format!("{}garbage", contents[1])).unwrap();
+
+        let args = DownloadArgs {
+            date: "20211101".to_string(),
+            output_dir: output_dir.to_str().unwrap().to_string(),
+            mirror: work_dir.join("mirror").to_str().unwrap().to_string(),
+            parts: Vec::new(),
+            verify_existing: false
+        };
+        let error = download(&args).await.expect_err("Part 3 should not match its checksum");
+        assert!(error.to_string().contains(&part_name(3)));
+
+        assert_eq!(std::fs::read_to_string(output_dir.join(part_name(1))).unwrap(), contents[0]);
+        assert!(!output_dir.join(part_name(1)).with_extension("7z.part").exists());
+        assert_eq!(std::fs::read_to_string(output_dir.join(part_name(2))).unwrap(), contents[1]);
+        assert!(!output_dir.join(part_name(3)).exists());
+        assert!(!output_dir.join(part_name(3)).with_extension("7z.part").exists());
+
+        std::fs::remove_dir_all(&work_dir).unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+mod config;
+mod download;
+mod stats;
+
+use crate::config::{parse_step, WdhistConfig};
+use crate::download::{download, DownloadArgs};
+use crate::stats::{stats, StatsArgs};
+
+use std::time::Instant;
+
+use clap::{Parser, Subcommand};
+use diff_indexer::{run_export, run_index, run_migrate, BackendError, ExportArgs, IndexArgs};
+
+
+/// Fetches the edit history of Wikidata: downloads the meta history dumps, computes the diff of each revision,
+/// indexes the diffs and exports them back to files. Exits with status 1 if any step fails, and 2 if the
+/// arguments are invalid.
+#[derive(Parser, Debug)]
+#[clap(author, version, about, long_about = None)]
+struct Args {
+    #[clap(subcommand)]
+    command: Commands,
+
+    /// TOML config file shared by every subcommand, with the connection settings and the options of the
+    /// steps of the run subcommand (see wdhist.example.toml)
+    #[clap(long, global = true)]
+    config: Option<String>,
+
+    /// MongoDB connection string. Overrides the MONGO_URI and MONGO_URL environment variables and the config file
+    #[clap(long, global = true)]
+    mongo_uri: Option<String>,
+
+    /// Database to index into. Overrides the MONGO_DB environment variable and the config file
+    #[clap(long, global = true)]
+    database: Option<String>
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Downloads the meta history dump files of a dump release, resuming partial downloads and verifying their
+    /// checksums
+    Download(DownloadArgs),
+    /// Computes the diff of each revision of the meta history dump files (see wd_diff_calculator)
+    Diff(wd_diff_calculator::Args),
+    /// Indexes the diff files (see diff_indexer)
+    Index(Box<IndexArgs>),
+    /// Prints statistics of the diff files: entities, revisions, time range and ops by type and section
+    Stats(StatsArgs),
+    /// Exports the entities and revisions indexed in MongoDB to JSON Lines files that can be indexed again
+    Export(ExportArgs),
+    /// Converts the documents indexed by previous versions to the current schema
+    Migrate,
+    /// Runs the download, diff and index steps with the options of the [download], [diff] and [index] tables
+    /// of the config file. Steps without a table are skipped.
+    Run
+}
+
+#[tokio::main]
+async fn main() -> Result<(), BackendError> {
+    let args = Args::parse();
+
+    // every failure exits with status 1, whether it is an error or a panic of one of the tools
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        std::process::exit(1);
+    }));
+
+    let (mut config, steps) = WdhistConfig::load(args.config.as_deref()).split();
+    config.mongo.override_with(args.mongo_uri, args.database);
+
+    let start = Instant::now();
+    match args.command {
+        Commands::Download(download_args) => download(&download_args).await?,
+        Commands::Diff(diff_args) => wd_diff_calculator::run(diff_args),
+        Commands::Index(index_args) => run_index(config, *index_args).await?,
+        Commands::Stats(stats_args) => stats(&stats_args)?,
+        Commands::Export(export_args) => run_export(&config, export_args).await?,
+        Commands::Migrate => run_migrate(&config).await?,
+        Commands::Run => {
+            // parse every step before running the first one, so mistakes in the config file are found early
+            let download_args = (!steps.download.is_empty())
+                .then(|| parse_step::<DownloadArgs>("download", &steps.download));
+            let diff_args = (!steps.diff.is_empty())
+                .then(|| parse_step::<wd_diff_calculator::Args>("diff", &steps.diff));
+            let index_args = (!steps.index.is_empty())
+                .then(|| parse_step::<IndexArgs>("index", &steps.index));
+
+            if let Some(download_args) = download_args {
+                println!("Downloading dumps...");
+                download(&download_args).await?;
+                println!("Dumps downloaded");
+            }
+            if let Some(diff_args) = diff_args {
+                println!("Computing diffs...");
+                wd_diff_calculator::run(diff_args);
+                println!("Diffs computed");
+            }
+            if let Some(index_args) = index_args {
+                println!("Indexing diffs...");
+                run_index(config, index_args).await?;
+                println!("Diffs indexed");
+            }
+        }
+    }
+    println!("Done in {:.1?}", start.elapsed());
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::BufWriter;
+
+use indicatif::{ProgressBar, ProgressIterator, ProgressStyle};
+use serde::Serialize;
+use wd_history_core::io::{get_diff_files, get_dump_version, get_file_name};
+use wd_history_core::op_path::OpPath;
+use wd_history_core::shard::{read_shard, ShardError};
+
+#[derive(clap::Args, Debug)]
+pub struct StatsArgs {
+    /// Input directory where the diff files are stored
+    #[clap(short, long)]
+    input_dir: String,
+
+    /// JSON file where the statistics are also saved
+    #[clap(long)]
+    output_file: Option<String>
+}
+
+/// Summary of the contents of the diff files of a directory
+#[derive(Debug, Default, Serialize)]
+pub struct DiffStats {
+    num_files: usize,
+    num_entities: usize,
+    num_revisions: usize,
+    num_ops: usize,
+    /// Number of files of each format version
+    format_versions: BTreeMap<u32, usize>,
+    /// Dates of the dumps the files were computed from
+    dump_dates: BTreeSet<String>,
+    first_timestamp: Option<String>,
+    last_timestamp: Option<String>,
+    /// Entity with the most revisions, and their number
+    most_edited_entity: Option<(String, usize)>,
+    /// Number of ops of each JSON Patch operation type
+    ops_by_type: BTreeMap<String, usize>,
+    /// Number of ops of each section of the entity JSON (claims, labels...)
+    ops_by_section: BTreeMap<String, usize>
+}
+
+/// Computes the statistics of the diff files of the input directory, prints them and saves them to the
+/// output file, if given
+pub fn stats(args: &StatsArgs) -> Result<(), ShardError> {
+    let files = get_diff_files(&args.input_dir);
+    let style = ProgressStyle::default_bar()
+        .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} files (ETA {eta})")
+        .progress_chars("##-");
+    let pb = ProgressBar::new(files.len() as u64);
+    pb.set_style(style);
+
+    let mut stats = DiffStats::default();
+    for path in files.iter().progress_with(pb) {
+        let (header, items) = read_shard(path)?;
+        stats.num_files += 1;
+        *stats.format_versions.entry(header.format_version).or_default() += 1;
+        if let Some(dump_date) = header.dump_date.or_else(|| get_dump_version(&get_file_name(path))) {
+            stats.dump_dates.insert(dump_date);
+        }
+
+        for item in items {
+            stats.num_entities += 1;
+            stats.num_revisions += item.revisions.len();
+            if stats.most_edited_entity.as_ref().is_none_or(|(_, n)| item.revisions.len() > *n) {
+                stats.most_edited_entity = Some((item.entity_id.clone(), item.revisions.len()));
+            }
+
+            for revision in item.revisions.iter() {
+                // RFC 3339 timestamps in UTC sort chronologically as strings
+                if stats.first_timestamp.as_ref().is_none_or(|t| revision.timestamp < *t) {
+                    stats.first_timestamp = Some(revision.timestamp.clone());
+                }
+                if stats.last_timestamp.as_ref().is_none_or(|t| revision.timestamp > *t) {
+                    stats.last_timestamp = Some(revision.timestamp.clone());
+                }
+
+                for op in revision.entity_diff.iter().flatten() {
+                    stats.num_ops += 1;
+                    *stats.ops_by_type.entry(op.op.name().to_string()).or_default() += 1;
+                    let section = OpPath::parse(&op.path, op.value.as_ref()).section.unwrap_or_default();
+                    *stats.ops_by_section.entry(section).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    print_stats(&stats);
+    if let Some(output_file) = &args.output_file {
+        let file = File::create(output_file).unwrap_or_else(|e| panic!("Could not create file {}: {}", output_file, e));
+        serde_json::to_writer_pretty(BufWriter::new(file), &stats)
+            .unwrap_or_else(|e| panic!("Error writing file {}: {}", output_file, e));
+        println!("Statistics saved to {}", output_file);
+    }
+    Ok(())
+}
+
+fn print_stats(stats: &DiffStats) {
+    println!("Files: {}", stats.num_files);
+    for (version, num_files) in stats.format_versions.iter() {
+        println!("  format version {}: {}", version, num_files);
+    }
+    println!("Dump dates: {}", stats.dump_dates.iter().cloned().collect::<Vec<String>>().join(", "));
+    println!("Entities: {}", stats.num_entities);
+    println!("Revisions: {}", stats.num_revisions);
+    if let (Some(first), Some(last)) = (&stats.first_timestamp, &stats.last_timestamp) {
+        println!("  from {} to {}", first, last);
+    }
+    if let Some((entity_id, num_revisions)) = &stats.most_edited_entity {
+        println!("  most edited entity: {} ({} revisions)", entity_id, num_revisions);
+    }
+    println!("Ops: {}", stats.num_ops);
+    for (op, num_ops) in stats.ops_by_type.iter() {
+        println!("  {}: {}", op, num_ops);
+    }
+    println!("Ops by section:");
+    for (section, num_ops) in stats.ops_by_section.iter() {
+        println!("  {}: {}", if section.is_empty() { "(whole entity)" } else { section }, num_ops);
+    }
+}